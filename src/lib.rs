@@ -2,16 +2,31 @@ use std::path::{Path, PathBuf};
 use mcp_attr::Result;
 use mcp_attr::server::{mcp_server, McpServer};
 use mcp_attr::schema::{GetPromptResult, CallToolResult};
-use std::sync::Mutex;
-use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::collections::{BTreeMap, HashMap};
 use serde::Deserialize;
 use schemars::JsonSchema;
-use reqwest;
 use crate::mcp::crates_io::{CratesIoClient, RequestOptions, FetchResponse};
+use crate::mcp::cargo::{self as cargo_cmd, CargoOptions, CargoSubcommand};
+use crate::mcp::diagnostics;
+use crate::mcp::shell::{self, ShellSession};
+use crate::mcp::jobs::{self, JobList};
+use crate::mcp::roots::AllowedRoots;
+use crate::mcp::mode::{self, ServerMode};
+use crate::mcp::policy::{self, Action, CommandPolicy};
+use crate::mcp::process;
+use crate::mcp::test_runner;
+use crate::mcp::read;
+use crate::mcp::tree;
+use crate::mcp::search;
+use crate::mcp::walk;
+use crate::mcp::edit;
 use crate::mcp::function_signatures;
-use crate::mcp::patch::{parse_hunks, find_candidates, rebuild_hunks, rebuild_patch};
+use crate::mcp::treesitter::{self, ProjectStructure};
+use crate::mcp::patch::{self, apply_patch, split_patch};
 use std::fs;
-use std::process::Command;
+use std::process::Output;
+use std::time::Duration;
 use crate::mcp::prompts::{CODE_CHANGE_WORKFLOW, MCP_TOOLS_GUIDE};
 
 
@@ -58,7 +73,25 @@ struct LookupCrateDocsArgs {
 
 pub struct ServerData {
     pub current_working_dir: PathBuf,
+    /// Directories the file tools and `cd` in `execute_bash` may access
+    pub allowed_roots: AllowedRoots,
     pub http_client: reqwest::Client,
+    /// The tests that failed in the last `run_tests` call
+    pub last_failed_tests: Vec<test_runner::FailedTest>,
+    /// Default timeout for `execute_bash` commands, and the timeout of the cargo commands tools run
+    pub command_timeout: Duration,
+    /// Maximum bytes of stdout and stderr returned per command
+    pub max_output_bytes: usize,
+    /// Persistent shell sessions used by `execute_bash`, by name
+    pub shell_sessions: HashMap<String, Arc<tokio::sync::Mutex<ShellSession>>>,
+    /// Background jobs started with `start_job`
+    pub jobs: JobList,
+    /// Which tools are available; in restricted mode commands must match `allowed_commands`
+    pub mode: ServerMode,
+    /// Command prefixes `execute_bash` and `start_job` may run in restricted mode
+    pub allowed_commands: Vec<String>,
+    /// Allow, ask and deny rules for the commands `execute_bash` and `start_job` run
+    pub command_policy: CommandPolicy,
 }
 
 pub struct CorrodeMcpServer(pub Mutex<ServerData>);
@@ -192,128 +225,364 @@ impl McpServer for CorrodeMcpServer {
     // --- Tool Implementations ---
 
     /// Execute a command using bash shell. Handles 'cd' to change server's working directory.
+    ///
+    /// Commands run in a persistent bash session, so environment variables, `cd`, `source`d
+    /// files, functions, aliases and activated virtualenvs carry over to later calls. Quoting,
+    /// `&&`/`;`/`||`, loops and heredocs behave exactly as in a terminal. The working directory
+    /// of the default session is the server's working directory used by the other tools.
+    ///
+    /// Commands are killed (including any child processes) when they exceed the timeout, which
+    /// also resets the session. Very long output is truncated in the middle, keeping its
+    /// beginning and end.
+    ///
+    /// A `cd` outside the allowed roots is only noticed after the command has run; the working
+    /// directory is then reset. This is not a sandbox: the command itself can still read and
+    /// write files anywhere the server's user can.
     #[tool] 
-    async fn execute_bash(&self, command: String) -> Result<CallToolResult> { 
-        let mut result = String::new();
-
-        // Split commands if they contain && or ;
-        let commands: Vec<&str> = if command.contains("&&") {
-            command.split("&&").collect()
-        } else if command.contains(';') {
-            command.split(';').collect()
-        } else {
-            vec![&command]
+    async fn execute_bash(&self,
+        /// The command to execute
+        command: String,
+        /// Seconds to wait before killing the command, defaults to the server's command timeout
+        timeout_secs: Option<u64>,
+        /// Name of the shell session to run in, each with its own environment and working directory (defaults to "default")
+        session: Option<String>) -> Result<CallToolResult> { 
+        let session_name = session.unwrap_or_else(|| shell::DEFAULT_SESSION.to_string());
+        let (current_dir_path, timeout, max_output_bytes, existing) = {
+            let server_state = self.0.lock().unwrap();
+            check_command_permitted(&server_state, &command)?;
+            let timeout = timeout_secs.map_or(server_state.command_timeout, Duration::from_secs);
+            (
+                server_state.current_working_dir.clone(),
+                timeout,
+                server_state.max_output_bytes,
+                server_state.shell_sessions.get(&session_name).cloned(),
+            )
         };
 
-        // Lock the state once for the duration of processing this command sequence
-        let mut server_state = self.0.lock().unwrap();
+        if let Err(e) = shell::check_syntax(&command).await {
+            mcp_attr::bail!("The command was not run because it has a syntax error:\n{}", e);
+        }
 
-        for cmd in commands {
-            let cmd = cmd.trim();
-            let current_dir_path = server_state.current_working_dir.clone(); 
-
-            // Check if command is a cd command and update working directory if it is
-            if let Some(new_dir) = handle_cd_command(&current_dir_path, cmd) {
-                // Try to actually change to this directory to verify it exists
-                if new_dir.exists() && new_dir.is_dir() {
-                    // Update the server state's CWD
-                    server_state.current_working_dir = new_dir.clone();
-                    result.push_str(&format!("Changed directory to: {}\n", new_dir.display()));
-                } else {
-                    // Enhanced error message for CD failures with more context
-                    let error_message = format!(
-                        "Directory change failed:\n- Command: '{}'\n- Target: {}\n- Current directory: {}\n- Error: The specified directory does not exist or is not accessible",
-                        cmd,
-                        new_dir.display(),
-                        current_dir_path.display()
-                    );
-                    
-                    result.push_str(&format!("{}\n", error_message));
-                    
-                    // Stop executing further commands if cd fails
-                    // Use bail! which converts to the appropriate error type for Result<CallToolResult>
-                    mcp_attr::bail!("{}", error_message);
-                }
+        let mut result = format!("$ {}\n", command);
+
+        let shell_session = match existing {
+            Some(shell_session) => shell_session,
+            None => {
+                let shell_session = Arc::new(tokio::sync::Mutex::new(start_shell(&current_dir_path).await?));
+                self.0
+                    .lock()
+                    .unwrap()
+                    .shell_sessions
+                    .insert(session_name.clone(), shell_session.clone());
+                shell_session
+            }
+        };
+        let mut shell_guard = shell_session.lock().await;
+        if !shell_guard.is_alive() {
+            *shell_guard = start_shell(&current_dir_path).await?;
+            result.push_str(&format!(
+                "The previous shell session had exited; started a new one in {}\n",
+                current_dir_path.display()
+            ));
+        }
 
-                // If this is a pure cd command, we're done with this part of the sequence
-                if cmd == "cd" || (cmd.starts_with("cd ") && !cmd.contains("&&") && !cmd.contains(';')) {
-                     continue;
+        let previous_dir = shell_guard.cwd().to_path_buf();
+        let output = match shell_guard.run(&command, timeout, max_output_bytes).await {
+            Ok(output) => output,
+            Err(e) => {
+                mcp_attr::bail!(
+                    "Failed to execute command '{}':\n- Error: {}\n- Working Directory: {}\n- Note: The shell session may have exited; it is restarted on the next call",
+                    command,
+                    e,
+                    current_dir_path.display()
+                );
+            }
+        };
+
+        // `cd` outside the allowed roots is undone afterwards so later commands start inside them.
+        // The command itself has already run unconfined
+        let mut denied_dir = None;
+        if let Some(new_dir) = output.cwd.as_ref().filter(|dir| **dir != previous_dir) {
+            let roots = self.0.lock().unwrap().allowed_roots.clone();
+            if let Err(e) = roots.check(new_dir) {
+                let cd_back = format!("cd -- {}", shell::quote(&previous_dir.to_string_lossy()));
+                if shell_guard.run(&cd_back, timeout, max_output_bytes).await.is_err() {
+                    shell_guard.kill();
                 }
+                denied_dir = Some(e);
+            }
+        }
+        drop(shell_guard);
+
+        if output.session_ended {
+            let mut server_state = self.0.lock().unwrap();
+            if server_state
+                .shell_sessions
+                .get(&session_name)
+                .is_some_and(|s| Arc::ptr_eq(s, &shell_session))
+            {
+                server_state.shell_sessions.remove(&session_name);
             }
+        }
 
-            // For non-cd commands or combined commands, execute with proper working directory
-            // Use the potentially updated current_dir_path for this specific command execution
-            let output = Command::new("bash")
-                .arg("-l") // Run as a login shell to load full environment
-                .current_dir(&current_dir_path) // Use the CWD relevant to this command
-                .arg("-c")
-                .arg(cmd) // Execute the potentially non-cd part
-                .output();
+        if let Some(e) = &denied_dir {
+            result.push_str(&format!(
+                "{}\nThe command ran, but the working directory was reset to {}. Only the working directory is confined to the allowed roots; commands are not sandboxed.\n",
+                e,
+                previous_dir.display()
+            ));
+        } else if session_name == shell::DEFAULT_SESSION
+            && let Some(new_dir) = output.cwd.clone().filter(|dir| dir.is_dir() && *dir != current_dir_path)
+        {
+            result.push_str(&format!("Changed directory to: {}\n", new_dir.display()));
+            self.0.lock().unwrap().current_working_dir = new_dir;
+        }
 
-            match output {
-                Ok(output) => {
-                    let stdout = String::from_utf8_lossy(&output.stdout).to_string();
-                    let stderr = String::from_utf8_lossy(&output.stderr).to_string();
+        if output.timed_out {
+            result.push_str(&format!(
+                "Timed out after {} seconds; the command and its child processes were killed and the shell session was reset.\n",
+                timeout.as_secs()
+            ));
+        } else {
+            result.push_str(&format!("Exit code: {}\n", output.exit_code.unwrap_or(-1)));
+        }
 
-                    let cmd_result = format!("$ {}\n", cmd);
-                    result.push_str(&cmd_result);
+        if !output.stdout.is_empty() {
+            result.push_str(&format!("\nStandard output:\n{}", output.stdout));
+        }
 
-                    let exit_status = output.status.code().unwrap_or(-1);
-                    let cmd_is_error = !output.status.success();
-                    // Store error status but continue accumulating output unless it's a fatal error
+        if !output.stderr.is_empty() {
+            result.push_str(&format!("\nStandard error:\n{}\n", output.stderr));
+        }
 
-                    result.push_str(&format!("Exit code: {}\n", exit_status));
+        if output.stdout_dropped > 0 || output.stderr_dropped > 0 {
+            result.push_str(&format!(
+                "\nOutput truncated: {} bytes of stdout and {} bytes of stderr were dropped.\n",
+                output.stdout_dropped, output.stderr_dropped
+            ));
+        }
 
-                    if !stdout.is_empty() {
-                        result.push_str(&format!("\nStandard output:\n{}", stdout));
-                    }
+        if output.session_ended && !output.timed_out {
+            result.push_str("\nThe shell exited; the next command starts a new session with a fresh environment.\n");
+        }
 
-                    if !stderr.is_empty() {
-                        result.push_str(&format!("\nStandard error:\n{}\n", stderr));
-                    }
+        if !output.timed_out && !output.success() {
+            result.push_str(&format!("\nCommand failed with exit code {}.\n", output.exit_code.unwrap_or(-1)));
+        }
 
-                    // If a command fails, stop executing and return the accumulated output + error
-                    if cmd_is_error {
-                         // Include both stdout and stderr in the error message for better debugging
-                         let error_message = format!("Command '{}' failed with exit code {}.\n\nSTDOUT:\n{}\n\nSTDERR:\n{}",
-                             cmd,
-                             exit_status,
-                             stdout,
-                             stderr
-                         );
-                         
-                         // Use bail! which converts to the appropriate error type for Result<CallToolResult>
-                         result.push_str(&format!("{}", error_message));
-                    }
-                },
-                Err(e) => {
-                    // Enhanced error message for command execution failure
-                    let error_details = format!(
-                        "Failed to execute command '{}':\n- Error: {}\n- Working Directory: {}\n- Note: This typically happens when the command or shell is not found, or due to permissions issues",
-                        cmd,
-                        e,
-                        current_dir_path.display()
-                    );
-                    
-                    result.push_str(&format!("{}\n", error_details));
-                    
-                    // Use bail! which converts to the appropriate error type for Result<CallToolResult>
-                    mcp_attr::bail!("{}", error_details);
+        // Wrap the final string result in CallToolResult
+        Ok(CallToolResult::from(result))
+    }
+
+    /// Reset a shell session used by execute_bash.
+    ///
+    /// Kills the session's bash process and anything it started; the next command in the session
+    /// starts with a fresh environment in the server's working directory.
+    #[tool]
+    async fn reset_shell(&self,
+        /// Name of the session to reset (defaults to "default")
+        session: Option<String>) -> Result<CallToolResult> {
+        let session_name = session.unwrap_or_else(|| shell::DEFAULT_SESSION.to_string());
+        let (removed, current_dir) = {
+            let mut server_state = self.0.lock().unwrap();
+            (
+                server_state.shell_sessions.remove(&session_name),
+                server_state.current_working_dir.clone(),
+            )
+        };
+
+        let Some(removed) = removed else {
+            return Ok(CallToolResult::from(format!(
+                "Shell session '{}' is not running; the next command starts a new one in {}",
+                session_name,
+                current_dir.display()
+            )));
+        };
+        // A command still running in the session holds the lock; the session is killed when
+        // that command returns and drops the last reference
+        let still_running = match removed.try_lock() {
+            Ok(mut shell_guard) => {
+                shell_guard.kill();
+                false
+            }
+            Err(_) => true,
+        };
+
+        let mut result = format!(
+            "Reset shell session '{}'; the next command starts with a fresh environment in {}",
+            session_name,
+            current_dir.display()
+        );
+        if still_running {
+            result.push_str("\nA command is still running in the old session; it is killed once it finishes or times out.");
+        }
+        Ok(CallToolResult::from(result))
+    }
+
+    /// Start a command in the background and return immediately.
+    ///
+    /// Use for dev servers, watchers or long builds. The command runs with `bash -c` in the
+    /// server's working directory; its output is collected and can be read incrementally with
+    /// read_job_output. Background jobs are killed when the server exits.
+    #[tool]
+    async fn start_job(&self,
+        /// The command to run in the background
+        command: String) -> Result<CallToolResult> {
+        let mut server_state = self.0.lock().unwrap();
+        check_command_permitted(&server_state, &command)?;
+        let current_dir = server_state.current_working_dir.clone();
+        let job = match server_state.jobs.start(&command, &current_dir) {
+            Ok(job) => job,
+            Err(e) => mcp_attr::bail!(
+                "Failed to start background job '{}':\n- Error: {}\n- Working Directory: {}",
+                command,
+                e,
+                current_dir.display()
+            ),
+        };
+
+        Ok(CallToolResult::from(format!(
+            "Started {}\nWorking directory: {}\nUse read_job_output with job_id {} to follow its output.",
+            jobs::render_job_line(job),
+            job.cwd.display(),
+            job.id
+        )))
+    }
+
+    /// List background jobs with their status, runtime and amount of unread output.
+    #[tool]
+    async fn list_jobs(&self) -> Result<CallToolResult> {
+        let server_state = self.0.lock().unwrap();
+        let lines: Vec<String> = server_state
+            .jobs
+            .iter()
+            .map(|job| format!("{} ({} bytes unread)", jobs::render_job_line(job), job.pending_bytes()))
+            .collect();
+
+        if lines.is_empty() {
+            return Ok(CallToolResult::from("No background jobs.".to_string()));
+        }
+        Ok(CallToolResult::from(lines.join("\n")))
+    }
+
+    /// Read the output a background job produced since the last read.
+    ///
+    /// With `wait_secs`, waits up to that long for new output or for the job to exit.
+    #[tool]
+    async fn read_job_output(&self,
+        /// Id of the job, as returned by start_job
+        job_id: usize,
+        /// Seconds to wait for new output when there is none yet
+        wait_secs: Option<u64>) -> Result<CallToolResult> {
+        let deadline = tokio::time::Instant::now() + Duration::from_secs(wait_secs.unwrap_or(0));
+        loop {
+            {
+                let server_state = self.0.lock().unwrap();
+                let Some(job) = server_state.jobs.get(job_id) else {
+                    mcp_attr::bail!("No background job with id {}. Use list_jobs to see the running jobs.", job_id);
+                };
+                let done = job.pending_bytes() > 0 || job.status() != jobs::JobStatus::Running;
+                if done || tokio::time::Instant::now() >= deadline {
+                    let output = job.take_output(server_state.max_output_bytes);
+                    let result = format!("{}\n{}", jobs::render_job_line(job), jobs::render_output(&output));
+                    return Ok(CallToolResult::from(result));
                 }
             }
+            tokio::time::sleep(Duration::from_millis(200)).await;
         }
+    }
 
-        // Drop the lock explicitly before returning Ok
-        drop(server_state);
+    /// Write text to the standard input of a background job.
+    #[tool]
+    async fn write_job_stdin(&self,
+        /// Id of the job, as returned by start_job
+        job_id: usize,
+        /// Text to send; include a trailing newline to submit a line
+        input: String,
+        /// Close stdin after writing, signalling end of input
+        close: Option<bool>) -> Result<CallToolResult> {
+        let stdin = {
+            let server_state = self.0.lock().unwrap();
+            let Some(job) = server_state.jobs.get(job_id) else {
+                mcp_attr::bail!("No background job with id {}. Use list_jobs to see the running jobs.", job_id);
+            };
+            job.stdin()
+        };
 
-        // If all commands succeeded
-        // Wrap the final string result in CallToolResult
+        let close = close.unwrap_or(false);
+        if let Err(e) = jobs::write_stdin(&stdin, &input, close).await {
+            mcp_attr::bail!("Failed to write to the stdin of job {}: {}", job_id, e);
+        }
+
+        Ok(CallToolResult::from(format!(
+            "Wrote {} bytes to job {}{}",
+            input.len(),
+            job_id,
+            if close { " and closed its stdin" } else { "" }
+        )))
+    }
+
+    /// Stop a background job and remove it from the job list.
+    ///
+    /// Sends SIGTERM to the job and its child processes, and kills them if they are still
+    /// running after a few seconds. Returns the output not read yet.
+    #[tool]
+    async fn kill_job(&self,
+        /// Id of the job, as returned by start_job
+        job_id: usize) -> Result<CallToolResult> {
+        {
+            let server_state = self.0.lock().unwrap();
+            let Some(job) = server_state.jobs.get(job_id) else {
+                mcp_attr::bail!("No background job with id {}. Use list_jobs to see the running jobs.", job_id);
+            };
+            job.terminate();
+        }
+
+        let deadline = tokio::time::Instant::now() + jobs::TERMINATE_GRACE_PERIOD;
+        let mut killed = false;
+        loop {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            let server_state = self.0.lock().unwrap();
+            let Some(job) = server_state.jobs.get(job_id) else {
+                break;
+            };
+            if job.status() != jobs::JobStatus::Running {
+                break;
+            }
+            if tokio::time::Instant::now() >= deadline && !killed {
+                job.kill();
+                killed = true;
+            }
+        }
+
+        let mut server_state = self.0.lock().unwrap();
+        let max_output_bytes = server_state.max_output_bytes;
+        let Some(job) = server_state.jobs.remove(job_id) else {
+            mcp_attr::bail!("Job {} was removed while it was being stopped.", job_id);
+        };
+        let mut result = jobs::render_job_line(&job);
+        if killed {
+            result.push_str(&format!(
+                "\nThe job did not exit within {} seconds of SIGTERM and was killed.",
+                jobs::TERMINATE_GRACE_PERIOD.as_secs()
+            ));
+        }
+        result.push('\n');
+        result.push_str(&jobs::render_output(&job.take_output(max_output_bytes)));
         Ok(CallToolResult::from(result))
     }
 
     /// Replace content with a Unified format git patch.
     ///
-    /// Use this tool to make multiple edits in a file.
+    /// Use this tool to make multiple edits in a file, or in several files at once with a patch
+    /// like `git diff` produces. Paths in the `---`/`+++` headers are relative to the current
+    /// working directory. Files are created with `--- /dev/null` or `new file mode`, deleted with
+    /// `+++ /dev/null` or `deleted file mode`, and moved with `rename from`/`rename to` or
+    /// differing `---`/`+++` paths. Either every hunk is applied or none is, unless `partial` is
+    /// set. With `dry_run`, nothing is written and the repaired patch and resulting diff are
+    /// returned.
+    /// Reports for each hunk whether it applied at the claimed line, applied at an offset, or
+    /// failed, with the closest region of the file and its first differing line.
     #[tool]
     /// Here is an example of a Unified format git patch:
     ///
@@ -330,91 +599,191 @@ impl McpServer for CorrodeMcpServer {
     /// 
     /// ```
     async fn patch_file(&self,
-        /// Full path of the file
-        file_name: String,
+        /// Full path of the file; may be omitted when the patch has `---`/`+++` headers
+        file_name: Option<String>,
         /// Unified format git patch to apply
-        patch: String) -> Result<CallToolResult> {
-        // Get the current working directory
-        let current_dir = self.0.lock().unwrap().current_working_dir.clone();
-        let file_path_buf = resolve_path(&current_dir, &file_name);
-        let display_path = file_path_buf.display().to_string();
-
-        // Read the original content
-        let mut old_content = match fs::read_to_string(&file_path_buf) {
-            Ok(content) => content,
-            Err(e) => mcp_attr::bail!("Failed to read file {}: {}", display_path, e),
-        };
-
-        // Patches are very strict on the last line being a newline
-        if !old_content.ends_with('\n') {
-            old_content.push('\n');
+        patch: String,
+        /// Preview the repaired patch and resulting diff without changing any file
+        dry_run: Option<bool>,
+        /// Apply the hunks that match even if others fail, instead of applying nothing
+        partial: Option<bool>) -> Result<CallToolResult> {
+        let file_patches = split_patch(&patch);
+        if file_patches.is_empty() {
+            mcp_attr::bail!("Failed to parse patch: no hunks or file changes found. Hunks start with a line like `@@ -10,6 +10,7 @@`.");
+        }
+        if file_name.is_some() && file_patches.len() > 1 {
+            mcp_attr::bail!(
+                "The patch changes {} files, so file_name can't name the file. Omit file_name and give each file `---`/`+++` headers.",
+                file_patches.len()
+            );
         }
 
-        // Parse the patch hunks
-        let old_hunks = match parse_hunks(&patch) {
-            Ok(hunks) => hunks,
-            Err(e) => mcp_attr::bail!("Failed to parse patch: {}", e),
-        };
+        // Apply every file's patch in memory first, so nothing is written if one fails
+        let dry_run = dry_run.unwrap_or(false);
+        let partial = partial.unwrap_or(false);
+        let mut files = Vec::new();
+        for file_patch in &file_patches {
+            // file_name names the file of a single-file patch, whatever its headers say
+            let (source, target) = match &file_name {
+                Some(file_name) => (Some(file_name.as_str()), Some(file_name.as_str())),
+                None => (
+                    file_patch.old_path.as_deref().or(file_patch.new_path.as_deref()),
+                    file_patch.new_path.as_deref().or(file_patch.old_path.as_deref()),
+                ),
+            };
+            if source.is_none() && target.is_none() {
+                mcp_attr::bail!("The patch has no `---`/`+++` headers naming the file. Pass file_name.");
+            }
+            let source = match source.filter(|_| !file_patch.creates) {
+                Some(source) => Some(resolve_allowed_path(&self.0.lock().unwrap(), source)?),
+                None => None,
+            };
+            let target = match target.filter(|_| !file_patch.deletes) {
+                Some(target) => Some(resolve_allowed_path(&self.0.lock().unwrap(), target)?),
+                None => None,
+            };
+            let label = patch_label(source.as_deref(), target.as_deref());
+
+            if let Some(target) = &target
+                && source.as_ref() != Some(target)
+                && target.exists()
+            {
+                mcp_attr::bail!("Failed to patch {}: {} already exists", label, target.display());
+            }
+            let old_content = match &source {
+                Some(source) => match fs::read_to_string(source) {
+                    Ok(content) => content,
+                    Err(e) => mcp_attr::bail!("Failed to read file {}: {}", source.display(), e),
+                },
+                None => String::new(),
+            };
 
-        // Find candidates for each hunk in the file
-        let candidates = find_candidates(&old_content, &old_hunks);
-        
-        // Rebuild the hunks with corrected line numbers
-        let new_hunks = rebuild_hunks(&candidates);
+            let applied = if file_patch.creates {
+                patch::create_file(&file_patch.text)
+            } else {
+                apply_patch(&old_content, &file_patch.text)
+            };
+            let applied = match applied {
+                Ok(applied) => applied,
+                Err(e) => mcp_attr::bail!("Failed to patch {}: {:#}", label, e),
+            };
+            if file_patch.deletes && file_patch.has_hunks() && applied.failed_count() == 0 && !applied.content.trim().is_empty() {
+                mcp_attr::bail!(
+                    "Failed to patch {}: the patch deletes the file, but {} lines are left after applying its hunks. Remove every line, or omit the hunks.",
+                    label,
+                    applied.content.lines().count()
+                );
+            }
+            files.push(PatchedFile { source, target, old_content, applied });
+        }
 
-        // Rebuild the patch with correct line numbers
-        let updated_patch = match rebuild_patch(&patch, &new_hunks) {
-            Ok(patch) => patch,
-            Err(e) => mcp_attr::bail!("Failed to render fixed patch: {}", e),
-        };
+        let total_hunks: usize = files.iter().map(|file| file.applied.hunks.len()).sum();
+        let failed_hunks: usize = files.iter().map(|file| file.applied.failed_count()).sum();
+        let failed_files: Vec<String> = files.iter().filter(|file| file.applied.failed_count() > 0).map(|file| file.label()).collect();
+        let current_dir = self.0.lock().unwrap().current_working_dir.clone();
 
-        // Parse the patch using diffy
-        let diffy_patch = match diffy::Patch::from_str(&updated_patch) {
-            Ok(patch) => patch,
-            Err(e) => mcp_attr::bail!("Failed to parse patch: {}", e),
+        let mut report = String::new();
+        for file in &files {
+            let applied = &file.applied;
+            report.push_str(&format!("\n{}:\n{}", file.label(), patch::render_hunk_reports(&applied.reports)));
+            if applied.hunks.is_empty() {
+                report.push_str("  No content changes\n");
+            }
+            if dry_run
+                && !applied.repaired.is_empty()
+                && let Some(path) = file.path()
+            {
+                let relative = path.strip_prefix(&current_dir).unwrap_or(path).display().to_string();
+                report.push_str(&format!(
+                    "\nRepaired patch:\n```patch\n{}```\n\nResulting diff:\n```diff\n{}```\n",
+                    applied.patch,
+                    patch::render_diff(&relative, &file.old_content, &applied.content)
+                ));
+            }
+        }
+        let json_report: Vec<serde_json::Value> = files
+            .iter()
+            .map(|file| {
+                let mut entry = serde_json::json!({
+                    "path": file.path().map(|path| path.display().to_string()),
+                    "change": file.change(),
+                    "hunks": file.applied.reports,
+                });
+                if let (Some(source), "rename") = (&file.source, file.change()) {
+                    entry["from"] = serde_json::json!(source.display().to_string());
+                }
+                entry
+            })
+            .collect();
+        let json = match serde_json::to_string_pretty(&json_report) {
+            Ok(json) => json,
+            Err(e) => mcp_attr::bail!("Error serializing patch report: {}", e),
         };
 
-        // Apply the patch
-        let patched = match diffy::apply(&old_content, &diffy_patch) {
-            Ok(patched) => patched,
-            Err(e) => mcp_attr::bail!("Failed to apply patch: {}", e),
+        // All or nothing, unless partial application was asked for; files where no hunk
+        // matched, and files to delete whose hunks don't all match, are never written
+        let to_write: Vec<&PatchedFile> = files
+            .iter()
+            .filter(|file| {
+                let applied = &file.applied;
+                if applied.failed_count() == 0 {
+                    partial || failed_hunks == 0
+                } else {
+                    partial && !applied.repaired.is_empty() && file.target.is_some()
+                }
+            })
+            .collect();
+        let changes: Vec<(PathBuf, Option<String>, Option<String>)> = to_write.iter().flat_map(|file| file.changes()).collect();
+        let written: Vec<String> = to_write.iter().map(|file| file.label()).collect();
+
+        let summary = if dry_run {
+            match (failed_hunks, partial) {
+                (0, _) => "Dry run: the patch applies cleanly. No files were changed.".to_string(),
+                (_, true) => format!(
+                    "Dry run: {} of {} hunks would be applied; the failed hunks would be skipped. No files were changed.",
+                    total_hunks - failed_hunks,
+                    total_hunks
+                ),
+                (_, false) => format!(
+                    "Dry run: the patch would not be applied because {} of {} hunks failed to match {}. No files were changed.",
+                    failed_hunks, total_hunks, failed_files.join(", ")
+                ),
+            }
+        } else {
+            if let Err(e) = write_all_or_restore(&changes) {
+                mcp_attr::bail!("{}", e);
+            }
+            if failed_hunks == 0 {
+                format!("Patch applied successfully to {}", written.join(", "))
+            } else if written.is_empty() {
+                format!(
+                    "No changes were applied: {} of {} hunks could not be matched to {}. Make sure all lines are correct, and check that the changes have not been applied already. Set partial to apply the hunks that matched.",
+                    failed_hunks, total_hunks, failed_files.join(", ")
+                )
+            } else {
+                format!(
+                    "Partially applied the patch: {} of {} hunks applied to {}. The failed hunks were skipped.",
+                    total_hunks - failed_hunks,
+                    total_hunks,
+                    written.join(", ")
+                )
+            }
         };
 
-        // Write the patched content to the file
-        match fs::write(&file_path_buf, &patched) {
-            Ok(_) => {
-                if new_hunks.len() != old_hunks.len() {
-                    let failed = old_hunks
-                        .iter()
-                        .filter(|h| !new_hunks.iter().any(|h2| h2.body == h.body))
-                        .collect::<Vec<_>>();
-    
-                    return Ok(CallToolResult::from(format!(
-                        "Failed to apply all hunks. {} hunks failed to apply.\n\nThe following hunks failed to apply as their context lines could not be matched to the file, no changes were applied:\n\n---\n{}\n---\n\nMake sure all lines are correct. Are you also sure that the changes have not been applied already?",
-                        failed.len(),
-                        failed.iter().map(|h| h.body.as_str()).collect::<Vec<_>>().join("\n")
-                    )));
-                }
-    
-                Ok(CallToolResult::from(format!("Patch applied successfully to {}", display_path)))
-            },
-            Err(e) => mcp_attr::bail!("Error writing to file '{}': {}", display_path, e),
-        }
+        Ok(CallToolResult::from(vec![format!("{}\n{}", summary, report), json]))
     }
 
     /// Write content to a file using the current working directory. use this to write new files or completely overwrite existing files.
     #[tool]
     async fn write_file(&self, file_path: String, content: String) -> Result<CallToolResult> {
-        let current_dir = self.0.lock().unwrap().current_working_dir.clone();
-        let file_path_buf = resolve_path(&current_dir, &file_path);
+        let file_path_buf = resolve_allowed_path(&self.0.lock().unwrap(), &file_path)?;
         let display_path = file_path_buf.display().to_string();
 
-        if let Some(parent) = file_path_buf.parent() {
-            if !parent.exists() {
-                if let Err(e) = fs::create_dir_all(parent) {
-                    mcp_attr::bail!("Error creating directory structure for '{}': {}", display_path, e); // bail! handles conversion
-                }
-            }
+        if let Some(parent) = file_path_buf.parent()
+            && !parent.exists()
+            && let Err(e) = fs::create_dir_all(parent)
+        {
+            mcp_attr::bail!("Error creating directory structure for '{}': {}", display_path, e); // bail! handles conversion
         }
 
         match fs::write(&file_path_buf, &content) {
@@ -423,36 +792,299 @@ impl McpServer for CorrodeMcpServer {
         }
     }
 
-    /// Check code for errors after editing. For Rust projects, runs 'cargo check'.
+    /// Replace an exact string in a file.
+    ///
+    /// `old_string` must match the file exactly, including whitespace, and occur only once unless
+    /// `replace_all` is set. Returns the edited lines with their surroundings. Prefer this over
+    /// patch_file for targeted changes.
+    #[tool]
+    async fn edit_file(&self,
+        file_path: String,
+        /// Exact text to replace; include enough surrounding lines to make it unique
+        old_string: String,
+        /// Text to put in its place
+        new_string: String,
+        /// Replace every occurrence instead of requiring exactly one
+        replace_all: Option<bool>) -> Result<CallToolResult> {
+        let file_path_buf = resolve_allowed_path(&self.0.lock().unwrap(), &file_path)?;
+        let display_path = file_path_buf.display().to_string();
+
+        let content = match fs::read_to_string(&file_path_buf) {
+            Ok(content) => content,
+            Err(e) => mcp_attr::bail!("Error reading file '{}': {}", display_path, e),
+        };
+        let edit = match edit::replace(&content, &old_string, &new_string, replace_all.unwrap_or(false)) {
+            Ok(edit) => edit,
+            Err(e) => mcp_attr::bail!("No changes made to '{}': {}", display_path, e),
+        };
+        if let Err(e) = fs::write(&file_path_buf, &edit.content) {
+            mcp_attr::bail!("Error writing to file '{}': {}", display_path, e);
+        }
+
+        let count = edit.replaced.len();
+        Ok(CallToolResult::from(format!(
+            "Replaced {} occurrence{} in {}:\n\n{}",
+            count,
+            if count == 1 { "" } else { "s" },
+            display_path,
+            edit::render_edit(&edit)
+        )))
+    }
+
+    /// Check code for errors after editing. For Rust projects, runs 'cargo check' by default.
     /// Use this after making edits to verify your changes compile correctly.
+    ///
+    /// The cargo subcommand can be switched to clippy, build, test (compiles tests without
+    /// running them), fmt (checks formatting) or doc, with optional package, feature, target and
+    /// profile selection. Returns a summary with error and warning counts and each diagnostic's
+    /// level, code, location, labels, notes and suggested replacements, followed by the same
+    /// data as JSON.
     #[tool]
-    async fn check_code(&self) -> Result<CallToolResult> { 
-        let current_dir = self.0.lock().unwrap().current_working_dir.clone();
+    #[allow(clippy::too_many_arguments)]
+    async fn check_code(&self,
+        /// Cargo subcommand to run: check (default), clippy, build, test (compile only), fmt (check only) or doc
+        command: Option<CargoSubcommand>,
+        /// Only run for this package (workspace member)
+        package: Option<String>,
+        /// Run for all workspace members
+        workspace: Option<bool>,
+        /// Comma or space separated list of features to activate
+        features: Option<String>,
+        /// Activate all available features
+        all_features: Option<bool>,
+        /// Do not activate the `default` feature
+        no_default_features: Option<bool>,
+        /// Target triple to build for, e.g. `wasm32-unknown-unknown`
+        target: Option<String>,
+        /// Build profile, e.g. `release`
+        profile: Option<String>) -> Result<CallToolResult> {
+        let (current_dir, timeout) = {
+            let server_state = self.0.lock().unwrap();
+            (server_state.current_working_dir.clone(), server_state.command_timeout)
+        };
         let cargo_toml_path = current_dir.join("Cargo.toml");
 
         if !cargo_toml_path.exists() {
              mcp_attr::bail!("No Cargo.toml found in '{}'. This doesn't appear to be a Rust project.", current_dir.display()); // bail! handles conversion
         }
 
-        self.execute_bash("cargo check".to_string()).await // Returns Result<CallToolResult>
+        let subcommand = command.unwrap_or_default();
+        let options = CargoOptions { package, workspace, features, all_features, no_default_features, target, profile };
+        let (report, stderr) = run_cargo_diagnostics(&current_dir, subcommand, &options, timeout).await?;
+
+        let mut text = format!(
+            "$ cargo {}\n{}",
+            cargo_cmd::build_args(subcommand, &options).join(" "),
+            diagnostics::render_report(&report)
+        );
+        // Failures without compiler diagnostics (e.g. manifest errors) are only explained on stderr
+        if !report.success && report.diagnostics.is_empty() {
+            text.push_str(&format!("\nStandard error:\n{}\n", stderr));
+        }
+
+        let json = match serde_json::to_string_pretty(&report) {
+            Ok(json) => json,
+            Err(e) => mcp_attr::bail!("Error serializing diagnostics: {}", e),
+        };
+
+        Ok(CallToolResult::from(vec![text, json]))
+    }
+
+    /// Run 'cargo test' and report the result of every test.
+    ///
+    /// Returns the passed, failed and ignored tests by name, with the panic message and captured
+    /// output of each failure, followed by the same data as JSON. Per-test durations are only
+    /// reported on nightly toolchains, as stable libtest has no machine-readable output with
+    /// timings. Use `filter` to select tests, or `rerun_failed` to only rerun the tests that
+    /// failed last time, in the packages they failed in.
+    #[tool]
+    #[allow(clippy::too_many_arguments)]
+    async fn run_tests(&self,
+        /// Only run tests whose name contains this string
+        filter: Option<String>,
+        /// Only rerun the tests that failed in the previous run
+        rerun_failed: Option<bool>,
+        /// Only run for this package (workspace member)
+        package: Option<String>,
+        /// Run for all workspace members
+        workspace: Option<bool>,
+        /// Comma or space separated list of features to activate
+        features: Option<String>,
+        /// Activate all available features
+        all_features: Option<bool>,
+        /// Do not activate the `default` feature
+        no_default_features: Option<bool>,
+        /// Target triple to build for, e.g. `wasm32-unknown-unknown`
+        target: Option<String>,
+        /// Build profile, e.g. `release`
+        profile: Option<String>) -> Result<CallToolResult> {
+        let (current_dir, last_failed, timeout) = {
+            let server_state = self.0.lock().unwrap();
+            (
+                server_state.current_working_dir.clone(),
+                server_state.last_failed_tests.clone(),
+                server_state.command_timeout,
+            )
+        };
+        if !current_dir.join("Cargo.toml").exists() {
+            mcp_attr::bail!("No Cargo.toml found in '{}'. This doesn't appear to be a Rust project.", current_dir.display());
+        }
+
+        let options = CargoOptions { package, workspace, features, all_features, no_default_features, target, profile };
+        let rerun_failed = rerun_failed.unwrap_or(false);
+        // Each run is a `cargo test` with its options and libtest filters
+        let runs: Vec<(CargoOptions, Vec<String>)> = if rerun_failed {
+            if last_failed.is_empty() {
+                return Ok(CallToolResult::from("No failed tests recorded from a previous run_tests call."));
+            }
+            // Rerun failures in the package they failed in, so tests of the same name in other
+            // packages don't run too; all at once if the package of a failure is unknown
+            if last_failed.iter().all(|test| test.package.is_some()) {
+                let mut by_package: BTreeMap<String, Vec<String>> = BTreeMap::new();
+                for test in last_failed {
+                    if let Some(package) = test.package {
+                        by_package.entry(package).or_default().push(test.name);
+                    }
+                }
+                by_package
+                    .into_iter()
+                    .map(|(package, names)| (CargoOptions { package: Some(package), workspace: None, ..options.clone() }, names))
+                    .collect()
+            } else {
+                vec![(options, last_failed.into_iter().map(|test| test.name).collect())]
+            }
+        } else {
+            vec![(options, filter.into_iter().collect())]
+        };
+
+        // Only nightly toolchains accept libtest's JSON output, which has per-test durations
+        let json = toolchain_is_nightly(&current_dir, timeout).await;
+        let mut commands = Vec::new();
+        let mut tests = Vec::new();
+        let mut build_errors = Vec::new();
+        let mut success = true;
+        let mut stderr = String::new();
+        for (options, filters) in &runs {
+            let cargo_args = test_runner::build_args(options, filters, rerun_failed, json);
+            let output = run_cargo(&current_dir, &cargo_args, timeout).await?;
+
+            let run_stdout = String::from_utf8_lossy(&output.stdout);
+            let run_stderr = String::from_utf8_lossy(&output.stderr);
+            build_errors.extend(
+                diagnostics::parse_cargo_messages(&run_stdout)
+                    .into_iter()
+                    .filter(|d| d.level.starts_with("error")),
+            );
+            tests.extend(test_runner::parse_test_output(
+                &run_stdout,
+                &test_runner::suite_packages(&run_stdout, &run_stderr),
+            ));
+            success &= output.status.success();
+            stderr.push_str(&run_stderr);
+
+            let shown_args: Vec<&str> = cargo_args.iter().map(String::as_str).filter(|a| *a != "--message-format=json").collect();
+            commands.push(format!("$ cargo {}", shown_args.join(" ")));
+        }
+        let report = test_runner::build_report(tests, build_errors, success);
+
+        // Remember failures for `rerun_failed`, unless the tests never ran
+        if report.build_errors.is_empty() {
+            self.0.lock().unwrap().last_failed_tests = report
+                .tests
+                .iter()
+                .filter(|t| t.status == test_runner::TestStatus::Failed)
+                .map(|t| test_runner::FailedTest { package: t.package.clone(), name: t.name.clone() })
+                .collect();
+        }
+
+        let mut text = format!("{}\n{}", commands.join("\n"), test_runner::render_test_report(&report));
+        if !report.success && report.tests.is_empty() && report.build_errors.is_empty() {
+            text.push_str(&format!("\nStandard error:\n{}\n", stderr));
+        }
+
+        let json = match serde_json::to_string_pretty(&report) {
+            Ok(json) => json,
+            Err(e) => mcp_attr::bail!("Error serializing test report: {}", e),
+        };
+
+        Ok(CallToolResult::from(vec![text, json]))
+    }
+
+    /// Apply the compiler's machine-applicable suggestions to the source files.
+    ///
+    /// Runs 'cargo check' (or 'cargo clippy'), collects every suggestion rustc or clippy marks as
+    /// MachineApplicable and applies them like `cargo fix` would. Suggestions that overlap an
+    /// already applied edit, or that point at files outside the allowed roots, are skipped and
+    /// reported. Run `check_code` afterwards, as fixes can reveal further diagnostics.
+    #[tool]
+    async fn apply_suggestions(&self,
+        /// Use clippy lints in addition to compiler diagnostics (default false)
+        clippy: Option<bool>) -> Result<CallToolResult> {
+        let (current_dir, timeout, allowed_roots) = {
+            let server_state = self.0.lock().unwrap();
+            (server_state.current_working_dir.clone(), server_state.command_timeout, server_state.allowed_roots.clone())
+        };
+        if !current_dir.join("Cargo.toml").exists() {
+            mcp_attr::bail!("No Cargo.toml found in '{}'. This doesn't appear to be a Rust project.", current_dir.display());
+        }
+
+        let subcommand = if clippy.unwrap_or(false) { CargoSubcommand::Clippy } else { CargoSubcommand::Check };
+        let options = CargoOptions::default();
+        let (report, _) = run_cargo_diagnostics(&current_dir, subcommand, &options, timeout).await?;
+        let root = cargo_workspace_root(&current_dir, timeout).await;
+        let fixes = diagnostics::apply_machine_applicable(&root, &allowed_roots, &report.diagnostics);
+
+        let text = format!(
+            "$ cargo {}\n{}",
+            cargo_cmd::build_args(subcommand, &options).join(" "),
+            diagnostics::render_fix_report(&fixes)
+        );
+        let json = match serde_json::to_string_pretty(&fixes) {
+            Ok(json) => json,
+            Err(e) => mcp_attr::bail!("Error serializing fix report: {}", e),
+        };
+
+        Ok(CallToolResult::from(vec![text, json]))
     }
 
     /// Reads file content.
     ///
-    /// Returns the content of a file at the specified path.
-    /// Provides the complete file content without truncation.
+    /// Returns the content of a file at the specified path, or the lines between `start_line`
+    /// and `end_line`. Large files are returned a page at a time; the output then ends with a
+    /// marker naming the `start_line` to continue from. Latin-1 and UTF-16 files are decoded
+    /// with a note; binary files are described by their size, type and a hex preview.
     #[tool]
-    async fn read_file(&self, file_path: String) -> Result<CallToolResult> {
-        let current_dir = self.0.lock().unwrap().current_working_dir.clone();
-        let file_path_buf = resolve_path(&current_dir, &file_path);
+    async fn read_file(&self,
+        file_path: String,
+        /// First line to return, starting at 1
+        start_line: Option<usize>,
+        /// Last line to return, inclusive
+        end_line: Option<usize>,
+        /// Prefix each line with its line number, e.g. to cite exact lines in patch hunks
+        line_numbers: Option<bool>) -> Result<CallToolResult> {
+        let file_path_buf = resolve_allowed_path(&self.0.lock().unwrap(), &file_path)?;
         let display_path = file_path_buf.display().to_string();
 
-        match fs::read_to_string(&file_path_buf) {
-            Ok(content) => {
-                // Return the full content without any truncation
-                Ok(CallToolResult::from(content)) // Wrap
-            },
+        let bytes = match fs::read(&file_path_buf) {
+            Ok(bytes) => bytes,
             Err(e) => mcp_attr::bail!("Error reading file '{}': {}", display_path, e), // bail! handles conversion
+        };
+        let (content, note) = match read::decode(&bytes) {
+            read::Decoded::Text { content, note } => (content, note),
+            read::Decoded::Binary => return Ok(CallToolResult::from(read::describe_binary(&display_path, &bytes))),
+        };
+        let options = read::ReadOptions {
+            start_line,
+            end_line,
+            line_numbers: line_numbers.unwrap_or(false),
+            max_bytes: read::MAX_READ_BYTES,
+        };
+        match read::render_lines(&content, &options) {
+            Ok(text) => match note {
+                Some(note) => Ok(CallToolResult::from(format!("[{}]\n\n{}", note, text))),
+                None => Ok(CallToolResult::from(text)),
+            },
+            Err(e) => mcp_attr::bail!("Error reading file '{}': {}", display_path, e),
         }
     }
     // --- Crates.io Tool Implementations ---
@@ -635,7 +1267,7 @@ impl McpServer for CorrodeMcpServer {
         
         let signatures = if let Some(args) = args {
             if let Some(file_path) = args.file_path {
-                let file_path_buf = resolve_path(&current_dir, &file_path);
+                let file_path_buf = resolve_allowed_path(&self.0.lock().unwrap(), &file_path)?;
                 result_string.push_str(&format!("Checking specific file: {}\n\n", file_path_buf.display()));
                 
                 if !file_path_buf.exists() {
@@ -664,21 +1296,347 @@ impl McpServer for CorrodeMcpServer {
         result_string.push_str(&format!("Found {} function signatures:\n\n", signatures.len()));
         
         for sig in signatures {
-            // Format: path/to/file.rs:line_number - signature
-            let formatted_line = format!(
-                "{}:{}: {}\n",
+            // Format: path/to/file.rs:line_number: signature (in Parent)
+            let mut formatted_line = format!(
+                "{}:{}: {}",
                 sig.file_path,
                 sig.line_number,
                 sig.signature.trim() // Trim whitespace from the signature line
             );
+            if let Some(parent) = &sig.parent {
+                formatted_line.push_str(&format!(" (in {})", parent));
+            }
+            formatted_line.push('\n');
             result_string.push_str(&formatted_line);
         }
 
         Ok(CallToolResult::from(result_string))
     }
 
+    /// List a directory as a tree.
+    ///
+    /// Shows files with their sizes and directories with the number and total size of the files
+    /// below them. Files excluded by `.gitignore` or `.ignore`, `target/` and version control
+    /// directories are left out.
+    #[tool]
+    async fn list_directory(&self,
+        /// Directory to list, defaults to the current working directory
+        path: Option<String>,
+        /// Levels of the tree to show, defaults to 2
+        depth: Option<usize>) -> Result<CallToolResult> {
+        let current_dir = self.0.lock().unwrap().current_working_dir.clone();
+        let target = match path {
+            Some(path) => resolve_allowed_path(&self.0.lock().unwrap(), &path)?,
+            None => current_dir,
+        };
+        if !target.is_dir() {
+            mcp_attr::bail!("'{}' is not a directory", target.display());
+        }
+
+        let depth = depth.unwrap_or(tree::DEFAULT_TREE_DEPTH);
+        Ok(CallToolResult::from(tree::render_tree(&target, depth)))
+    }
+
+    /// Search file contents with a regular expression or literal text.
+    ///
+    /// Returns matches as `path:line:column: text` followed by the same data as JSON. Files
+    /// excluded by `.gitignore` or `.ignore`, `target/`, version control directories and binary
+    /// files are skipped.
+    #[tool]
+    #[allow(clippy::too_many_arguments)]
+    async fn search_code(&self,
+        /// Regular expression to search for, or literal text when `literal` is set
+        pattern: String,
+        /// File or directory to search, defaults to the current working directory
+        path: Option<String>,
+        /// Treat the pattern as literal text instead of a regular expression
+        literal: Option<bool>,
+        /// Match case exactly, defaults to true
+        case_sensitive: Option<bool>,
+        /// Only search files matching these globs, e.g. `*.rs` or `src/**/*.toml`. Globs without
+        /// `/` match file names at any depth; in others `*` doesn't match `/`
+        include: Option<Vec<String>>,
+        /// Skip files matching these globs
+        exclude: Option<Vec<String>>,
+        /// Lines of context to show around each match
+        context_lines: Option<usize>,
+        /// Maximum number of matching lines to return, defaults to 100
+        max_results: Option<usize>) -> Result<CallToolResult> {
+        let current_dir = self.0.lock().unwrap().current_working_dir.clone();
+        let target = match path {
+            Some(path) => resolve_allowed_path(&self.0.lock().unwrap(), &path)?,
+            None => current_dir.clone(),
+        };
+        if !target.exists() {
+            mcp_attr::bail!("Path '{}' does not exist", target.display());
+        }
+
+        let source = if literal.unwrap_or(false) { regex::escape(&pattern) } else { pattern.clone() };
+        let regex = match regex::RegexBuilder::new(&source)
+            .case_insensitive(!case_sensitive.unwrap_or(true))
+            .build()
+        {
+            Ok(regex) => regex,
+            Err(e) => mcp_attr::bail!("Invalid regular expression: {}", e),
+        };
+        let (include, exclude) = match (
+            search::build_globs(&include.unwrap_or_default()),
+            search::build_globs(&exclude.unwrap_or_default()),
+        ) {
+            (Ok(include), Ok(exclude)) => (include, exclude),
+            (Err(e), _) | (_, Err(e)) => mcp_attr::bail!("Invalid glob: {}", e),
+        };
+        let options = search::SearchOptions {
+            regex,
+            include,
+            exclude,
+            context_lines: context_lines.unwrap_or(0),
+            max_results: max_results.unwrap_or(search::DEFAULT_MAX_RESULTS),
+        };
+
+        let results = search::search(&target, &current_dir, &options);
+        if results.matches.is_empty() {
+            return Ok(CallToolResult::from(format!(
+                "No matches for `{}` in {} ({} files searched)",
+                pattern,
+                target.display(),
+                results.files_searched
+            )));
+        }
+
+        let mut summary = format!("{} matches", results.matches.len());
+        if results.truncated {
+            summary.push_str(&format!(" (stopped at max_results = {}; narrow the search to see more)", options.max_results));
+        }
+        let text = format!("{}\n\n{}", summary, results.rendered);
+        let json = match serde_json::to_string_pretty(&results.matches) {
+            Ok(json) => json,
+            Err(e) => mcp_attr::bail!("Error serializing matches: {}", e),
+        };
+        Ok(CallToolResult::from(vec![text, json]))
+    }
+
+    /// Find files by glob pattern, most recently modified first.
+    ///
+    /// Patterns are matched against paths relative to the search directory, e.g. `src/**/*.rs`
+    /// or `**/Cargo.toml`. Files excluded by `.gitignore` or `.ignore`, `target/` and version
+    /// control directories are skipped.
+    #[tool]
+    async fn find_files(&self,
+        /// Glob patterns; a file matching any of them is returned
+        patterns: Vec<String>,
+        /// Directory to search, defaults to the current working directory
+        path: Option<String>,
+        /// Maximum number of paths to return, defaults to 200
+        max_results: Option<usize>) -> Result<CallToolResult> {
+        let current_dir = self.0.lock().unwrap().current_working_dir.clone();
+        let target = match path {
+            Some(path) => resolve_allowed_path(&self.0.lock().unwrap(), &path)?,
+            None => current_dir.clone(),
+        };
+        if !target.is_dir() {
+            mcp_attr::bail!("'{}' is not a directory", target.display());
+        }
+        if patterns.is_empty() {
+            mcp_attr::bail!("No patterns given. Pass globs such as \"src/**/*.rs\" or \"**/Cargo.toml\".");
+        }
+
+        let found = match walk::find_files(&target, &patterns) {
+            Ok(found) => found,
+            Err(e) => mcp_attr::bail!("Invalid glob: {}", e),
+        };
+        if found.is_empty() {
+            return Ok(CallToolResult::from(format!(
+                "No files matching {} in {}",
+                patterns.join(", "),
+                target.display()
+            )));
+        }
+
+        let max_results = max_results.unwrap_or(walk::DEFAULT_MAX_FOUND_FILES);
+        let mut result: Vec<String> = found
+            .iter()
+            .take(max_results)
+            .map(|path| path.strip_prefix(&current_dir).unwrap_or(path).display().to_string())
+            .collect();
+        if found.len() > max_results {
+            result.push(format!(
+                "\n[... {} more files not shown. Use a narrower pattern or a larger max_results ...]",
+                found.len() - max_results
+            ));
+        }
+        Ok(CallToolResult::from(result.join("\n")))
+    }
+
+    /// Outline the structure of a source file or directory tree.
+    ///
+    /// Lists functions, structs, classes, enums, traits, impls and imports with their line ranges,
+    /// as a readable outline followed by the same data as JSON. Use this to orient yourself in a
+    /// project before reading individual files.
+    #[tool]
+    async fn project_outline(&self,
+        /// File or directory to outline, defaults to the current working directory
+        path: Option<String>) -> Result<CallToolResult> {
+        let current_dir = self.0.lock().unwrap().current_working_dir.clone();
+        let target = match path {
+            Some(path) => resolve_allowed_path(&self.0.lock().unwrap(), &path)?,
+            None => current_dir,
+        };
+
+        let structure = if target.is_dir() {
+            treesitter::parse_project(&target)
+        } else if target.is_file() {
+            let Some(info) = treesitter::parse_file(&target, None) else {
+                mcp_attr::bail!("Could not parse '{}': unsupported language or unreadable file", target.display());
+            };
+            ProjectStructure { files: [(info.path.clone(), info)].into_iter().collect() }
+        } else {
+            mcp_attr::bail!("Path '{}' does not exist", target.display());
+        };
+
+        if structure.files.is_empty() {
+            return Ok(CallToolResult::from(format!("No supported source files found in {}", target.display())));
+        }
+
+        let mut outline = format!("Outline of {} ({} files):\n\n", target.display(), structure.files.len());
+        for file in structure.files.values() {
+            outline.push_str(&treesitter::render_outline(file));
+            outline.push('\n');
+        }
+
+        let json = match serde_json::to_string_pretty(&structure) {
+            Ok(json) => json,
+            Err(e) => mcp_attr::bail!("Error serializing outline: {}", e),
+        };
+
+        Ok(CallToolResult::from(vec![outline, json]))
+    }
+
 }
 // Simplified Args struct
+// Helper function to write patched files, restoring the ones already written if a write fails
+fn write_all_or_restore(changes: &[(PathBuf, Option<String>, Option<String>)]) -> std::result::Result<(), String> {
+    for (i, (path, _, new_content)) in changes.iter().enumerate() {
+        let result = match new_content {
+            Some(content) => path
+                .parent()
+                .map_or(Ok(()), fs::create_dir_all)
+                .and_then(|_| fs::write(path, content)),
+            None => fs::remove_file(path),
+        };
+        if let Err(e) = result {
+            for (changed, old_content, _) in changes[..i].iter().rev() {
+                let _ = match old_content {
+                    Some(content) => fs::write(changed, content),
+                    None => fs::remove_file(changed),
+                };
+            }
+            return Err(format!(
+                "Error {} file '{}': {}. No changes were applied; files changed before the error were restored.",
+                if new_content.is_some() { "writing to" } else { "deleting" },
+                path.display(),
+                e
+            ));
+        }
+    }
+    Ok(())
+}
+
+// A file changed by patch_file: `source` is `None` when the patch creates it, `target` is
+// `None` when the patch deletes it, and they differ when it is renamed
+struct PatchedFile {
+    source: Option<PathBuf>,
+    target: Option<PathBuf>,
+    old_content: String,
+    applied: patch::AppliedPatch,
+}
+
+impl PatchedFile {
+    fn path(&self) -> Option<&Path> {
+        self.target.as_deref().or(self.source.as_deref())
+    }
+
+    fn change(&self) -> &'static str {
+        match (&self.source, &self.target) {
+            (None, _) => "create",
+            (_, None) => "delete",
+            (Some(source), Some(target)) if source != target => "rename",
+            _ => "modify",
+        }
+    }
+
+    fn label(&self) -> String {
+        patch_label(self.source.as_deref(), self.target.as_deref())
+    }
+
+    // The writes and deletions for write_all_or_restore
+    fn changes(&self) -> Vec<(PathBuf, Option<String>, Option<String>)> {
+        let content = Some(self.applied.content.clone());
+        match (&self.source, &self.target) {
+            (None, Some(target)) => vec![(target.clone(), None, content)],
+            (Some(source), None) => vec![(source.clone(), Some(self.old_content.clone()), None)],
+            (Some(source), Some(target)) if source != target => vec![
+                (target.clone(), None, content),
+                (source.clone(), Some(self.old_content.clone()), None),
+            ],
+            (Some(path), Some(_)) => vec![(path.clone(), Some(self.old_content.clone()), content)],
+            (None, None) => Vec::new(),
+        }
+    }
+}
+
+// Names a file in the patch_file report, e.g. `src/old.rs -> src/new.rs` for a rename
+fn patch_label(source: Option<&Path>, target: Option<&Path>) -> String {
+    match (source, target) {
+        (None, Some(target)) => format!("{} (new file)", target.display()),
+        (Some(source), None) => format!("{} (deleted)", source.display()),
+        (Some(source), Some(target)) if source != target => format!("{} -> {}", source.display(), target.display()),
+        (Some(path), Some(_)) => path.display().to_string(),
+        (None, None) => String::new(),
+    }
+}
+
+// Helper function to start a shell session for execute_bash
+#[allow(clippy::result_large_err)]
+async fn start_shell(current_dir: &Path) -> Result<ShellSession> {
+    match ShellSession::start(current_dir).await {
+        Ok(shell_session) => Ok(shell_session),
+        Err(e) => mcp_attr::bail!(
+            "Failed to start a shell session:\n- Error: {}\n- Working Directory: {}\n- Note: This typically happens when bash is not found, or due to permissions issues",
+            e,
+            current_dir.display()
+        ),
+    }
+}
+
+// Helper function to refuse commands that the restricted mode allowlist or the command policy
+// doesn't allow
+#[allow(clippy::result_large_err)]
+fn check_command_permitted(server_state: &ServerData, command: &str) -> Result<()> {
+    if server_state.mode == ServerMode::Restricted && !mode::command_allowed(command, &server_state.allowed_commands) {
+        mcp_attr::bail!(
+            "The command was not run: the server runs in restricted mode, which only allows single commands starting with one of: {}. Operators such as `;`, `&&`, `|`, redirections, variable assignments, `$` expansions and options that write files or run other programs, such as `--output`, `--fix`, `--target-dir` or `--config`, are not allowed.",
+            server_state.allowed_commands.join(", ")
+        );
+    }
+
+    let decision = server_state.command_policy.evaluate(command);
+    if decision.action != Action::Allow {
+        mcp_attr::bail!("{}", policy::render_refusal(&server_state.command_policy, &decision));
+    }
+    Ok(())
+}
+
+// Helper function to resolve a path for the file tools, refusing paths outside the allowed roots
+#[allow(clippy::result_large_err)]
+fn resolve_allowed_path(server_state: &ServerData, file_path: &str) -> Result<PathBuf> {
+    let path = resolve_path(&server_state.current_working_dir, file_path);
+    match server_state.allowed_roots.check(&path) {
+        Ok(resolved) => Ok(resolved),
+        Err(e) => mcp_attr::bail!("{}", e),
+    }
+}
+
 // Helper function to resolve a file path relative to the current directory
 pub fn resolve_path(current_dir: &Path, file_path: &str) -> PathBuf {
     if file_path.starts_with('/') {
@@ -694,24 +1652,70 @@ pub fn resolve_path(current_dir: &Path, file_path: &str) -> PathBuf {
     }
 }
 
-// Helper function to update working directory when cd commands are used
-// Takes current_dir as argument now
-pub fn handle_cd_command(current_dir: &Path, command: &str) -> Option<PathBuf> {
-    let command = command.trim();
-
-    // Check if command is a cd command or starts with cd and has more components
-    if command == "cd" || command.starts_with("cd ") {
-        let parts: Vec<&str> = command.splitn(2, ' ').collect();
-        if parts.len() == 1 {
-            // Just "cd", go to home directory
-            return Some(dirs::home_dir().unwrap_or_else(|| PathBuf::from(".")));
-        } else if parts.len() == 2 {
-            let dir = parts[1].trim();
-            // Handle different path types using resolve_path helper
-            let new_path = resolve_path(current_dir, dir);
-            return Some(new_path);
-        }
-    }
-    None
+// Helper function to run a cargo subcommand with JSON diagnostics, returning the report and stderr
+#[allow(clippy::result_large_err)]
+async fn run_cargo_diagnostics(
+    current_dir: &Path,
+    subcommand: CargoSubcommand,
+    options: &CargoOptions,
+    timeout: Duration,
+) -> Result<(diagnostics::DiagnosticReport, String)> {
+    let mut args = cargo_cmd::build_args(subcommand, options);
+    if subcommand.supports_json() {
+        args.push("--message-format=json".to_string());
+    }
+    let output = run_cargo(current_dir, &args, timeout).await?;
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let stderr = String::from_utf8_lossy(&output.stderr).to_string();
+    let parsed = if subcommand.supports_json() {
+        diagnostics::parse_cargo_messages(&stdout)
+    } else {
+        cargo_cmd::parse_fmt_check(&stdout)
+    };
+    let report = diagnostics::build_report(parsed, output.status.success());
+    Ok((report, stderr))
+}
+
+// Helper function to run cargo in its own process group, killing it and everything it started
+// (build scripts, test binaries) when it exceeds the timeout
+#[allow(clippy::result_large_err)]
+async fn run_cargo(current_dir: &Path, args: &[String], timeout: Duration) -> Result<Output> {
+    let mut command = tokio::process::Command::new("cargo");
+    command.args(args).env("RUST_BACKTRACE", "0").current_dir(current_dir);
+    match process::output_with_timeout(command, timeout).await {
+        Ok(Some(output)) => Ok(output),
+        Ok(None) => mcp_attr::bail!(
+            "cargo {} timed out after {} seconds; it and its child processes were killed.",
+            args.join(" "),
+            timeout.as_secs()
+        ),
+        Err(e) => mcp_attr::bail!("Failed to run cargo {} in '{}': {}", args.join(" "), current_dir.display(), e),
+    }
+}
+
+// Helper function to check whether the toolchain used in a directory is nightly, which is
+// required for libtest's JSON output
+async fn toolchain_is_nightly(current_dir: &Path, timeout: Duration) -> bool {
+    let mut command = tokio::process::Command::new("rustc");
+    command.arg("--version").current_dir(current_dir);
+    match process::output_with_timeout(command, timeout).await {
+        Ok(Some(output)) => String::from_utf8_lossy(&output.stdout).contains("nightly"),
+        _ => false,
+    }
+}
+
+// Helper function to find the cargo workspace root, which compiler diagnostic paths are relative to
+async fn cargo_workspace_root(current_dir: &Path, timeout: Duration) -> PathBuf {
+    let args = ["locate-project", "--workspace", "--message-format", "plain"].map(String::from);
+    run_cargo(current_dir, &args, timeout)
+        .await
+        .ok()
+        .filter(|output| output.status.success())
+        .and_then(|output| {
+            let manifest = PathBuf::from(String::from_utf8_lossy(&output.stdout).trim());
+            manifest.parent().map(Path::to_path_buf)
+        })
+        .unwrap_or_else(|| current_dir.to_path_buf())
 }
 
@@ -1,26 +1,131 @@
-mod mcp;
-use mcp_attr::{server::serve_stdio_with, Result};
+use mcp_attr::Result;
 use mcp_attr::server::serve_stdio;
 use std::sync::Mutex;
 use std::path::PathBuf;
+use std::collections::HashMap;
 use std::env;
-use reqwest; // Keep reqwest as it's used in http_client builder
+use std::time::Duration;
+use clap::{CommandFactory, Parser};
+use clap::error::ErrorKind;
 use corrode_mcp::{CorrodeMcpServer, ServerData};
+use corrode_mcp::mcp::jobs::JobList;
+use corrode_mcp::mcp::roots::AllowedRoots;
+use corrode_mcp::mcp::mode::{ModeServer, ServerMode, DEFAULT_ALLOWED_COMMANDS};
+use corrode_mcp::mcp::policy::CommandPolicy;
+use corrode_mcp::mcp::process::{DEFAULT_COMMAND_TIMEOUT, DEFAULT_MAX_OUTPUT_BYTES};
 
 
+/// Corrode Code MCP Server for Rust
+#[derive(Parser)]
+#[command(version, about)]
+struct Cli {
+    /// Accepted for compatibility with older client configurations; the server always speaks MCP over stdio
+    #[arg(long, hide = true)]
+    mcp: bool,
+
+    /// Directory the file tools and `cd` in execute_bash may access; repeat to allow several
+    /// (defaults to the startup directory)
+    #[arg(long = "allow-root", value_name = "DIR")]
+    allow_roots: Vec<PathBuf>,
+
+    /// Which tools are available: everything, read-only tools (check_code still compiles the
+    /// project), or read-only tools plus tests and allowlisted commands
+    #[arg(long, value_enum, default_value_t = ServerMode::Full)]
+    mode: ServerMode,
+
+    /// Command prefix execute_bash may run in restricted mode, e.g. "cargo test"; repeat to allow
+    /// several (defaults to common cargo and git inspection commands)
+    #[arg(long = "allow-command", value_name = "COMMAND")]
+    allow_commands: Vec<String>,
+
+    /// TOML file with allow, ask and deny rules for the commands execute_bash may run
+    #[arg(long, value_name = "FILE")]
+    policy: Option<PathBuf>,
+
+    /// Default timeout in seconds for commands run by execute_bash and the cargo commands tools run
+    #[arg(long, default_value_t = DEFAULT_COMMAND_TIMEOUT.as_secs())]
+    command_timeout: u64,
+
+    /// Maximum bytes of stdout and stderr returned per command; longer output is truncated in the middle
+    #[arg(long, default_value_t = DEFAULT_MAX_OUTPUT_BYTES)]
+    max_output_bytes: usize,
+}
+
 #[tokio::main]
+#[allow(clippy::result_large_err)]
 async fn main() -> Result<()> {
+    let cli = Cli::parse();
+
+    let startup_dir = env::current_dir().unwrap_or_else(|_| PathBuf::from("."));
+    let root_dirs = if cli.allow_roots.is_empty() { vec![startup_dir.clone()] } else { cli.allow_roots };
+    let allowed_roots = match AllowedRoots::new(root_dirs) {
+        Ok(roots) => roots,
+        Err(e) => Cli::command().error(ErrorKind::InvalidValue, e).exit(),
+    };
+    // Start in the first allowed root when the startup directory is outside all of them
+    let current_working_dir = match allowed_roots.check(&startup_dir) {
+        Ok(dir) => dir,
+        Err(_) => allowed_roots.roots()[0].clone(),
+    };
+
+    let command_policy = match &cli.policy {
+        Some(path) => match CommandPolicy::load(path) {
+            Ok(policy) => policy,
+            Err(e) => Cli::command().error(ErrorKind::InvalidValue, e).exit(),
+        },
+        None => CommandPolicy::default(),
+    };
 
     let server_data = ServerData {
-        current_working_dir: env::current_dir().unwrap_or_else(|_| PathBuf::from(".")),
+        current_working_dir,
+        allowed_roots,
         http_client: reqwest::Client::builder()
             .user_agent("corrode-mcp/0.0.2 (github.com/alexboehm/corrode-mcp)")
             .build()
             .unwrap_or_else(|_| reqwest::Client::new()),
+        last_failed_tests: Vec::new(),
+        command_timeout: Duration::from_secs(cli.command_timeout),
+        max_output_bytes: cli.max_output_bytes,
+        shell_sessions: HashMap::new(),
+        jobs: JobList::default(),
+        mode: cli.mode,
+        allowed_commands: if cli.allow_commands.is_empty() {
+            DEFAULT_ALLOWED_COMMANDS.iter().map(|c| c.to_string()).collect()
+        } else {
+            cli.allow_commands
+        },
+        command_policy,
+    };
+    let server = ModeServer {
+        inner: CorrodeMcpServer(Mutex::new(server_data)),
+        mode: cli.mode,
     };
-    let server = CorrodeMcpServer(Mutex::new(server_data));
 
-    serve_stdio(server).await?;
+    // Dropping the server kills background jobs and shell sessions, so stop serving on
+    // SIGINT/SIGTERM instead of letting the signal terminate the process
+    tokio::select! {
+        result = serve_stdio(server) => result?,
+        _ = shutdown_signal() => {}
+    }
 
     Ok(())
 }
+
+#[cfg(unix)]
+async fn shutdown_signal() {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let Ok(mut terminate) = signal(SignalKind::terminate()) else {
+        let _ = tokio::signal::ctrl_c().await;
+        return;
+    };
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => {}
+        _ = terminate.recv() => {}
+    }
+}
+
+#[cfg(not(unix))]
+async fn shutdown_signal() {
+    let _ = tokio::signal::ctrl_c().await;
+}
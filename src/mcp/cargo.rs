@@ -0,0 +1,142 @@
+use schemars::JsonSchema;
+use serde::Deserialize;
+
+use crate::mcp::diagnostics::{Diagnostic, Location, Note};
+
+/// The cargo subcommands `check_code` can run
+#[derive(Deserialize, JsonSchema, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum CargoSubcommand {
+    #[default]
+    Check,
+    Clippy,
+    Build,
+    /// Compiles the tests without running them (`cargo test --no-run`)
+    Test,
+    /// Checks formatting without modifying files (`cargo fmt --check`)
+    Fmt,
+    /// Builds the documentation of the workspace crates (`cargo doc --no-deps`)
+    Doc,
+}
+
+/// Package, feature and target selection shared by the cargo based tools
+#[derive(Clone, Debug, Default)]
+pub struct CargoOptions {
+    /// Only run for this package (workspace member)
+    pub package: Option<String>,
+    /// Run for all workspace members
+    pub workspace: Option<bool>,
+    /// Comma or space separated list of features to activate
+    pub features: Option<String>,
+    /// Activate all available features
+    pub all_features: Option<bool>,
+    /// Do not activate the `default` feature
+    pub no_default_features: Option<bool>,
+    /// Target triple to build for, e.g. `wasm32-unknown-unknown`
+    pub target: Option<String>,
+    /// Build profile, e.g. `release`
+    pub profile: Option<String>,
+}
+
+impl CargoSubcommand {
+    /// Whether the subcommand can report diagnostics with `--message-format=json`
+    pub fn supports_json(self) -> bool {
+        self != CargoSubcommand::Fmt
+    }
+}
+
+/// Builds the cargo arguments for a subcommand and the selected options
+///
+/// `cargo fmt` only understands package selection, so features, target and profile are
+/// not passed to it.
+pub fn build_args(subcommand: CargoSubcommand, options: &CargoOptions) -> Vec<String> {
+    let mut args: Vec<String> = match subcommand {
+        CargoSubcommand::Check => vec!["check".into()],
+        CargoSubcommand::Clippy => vec!["clippy".into()],
+        CargoSubcommand::Build => vec!["build".into()],
+        CargoSubcommand::Test => vec!["test".into(), "--no-run".into()],
+        CargoSubcommand::Fmt => vec!["fmt".into(), "--check".into()],
+        CargoSubcommand::Doc => vec!["doc".into(), "--no-deps".into()],
+    };
+
+    if let Some(package) = &options.package {
+        args.push("-p".into());
+        args.push(package.clone());
+    }
+    if options.workspace.unwrap_or(false) {
+        args.push(if subcommand == CargoSubcommand::Fmt { "--all" } else { "--workspace" }.into());
+    }
+
+    if subcommand == CargoSubcommand::Fmt {
+        // Keep the diff free of terminal color codes
+        args.extend(["--".into(), "--color".into(), "never".into()]);
+        return args;
+    }
+
+    if let Some(features) = &options.features {
+        args.push("--features".into());
+        args.push(features.clone());
+    }
+    if options.all_features.unwrap_or(false) {
+        args.push("--all-features".into());
+    }
+    if options.no_default_features.unwrap_or(false) {
+        args.push("--no-default-features".into());
+    }
+    if let Some(target) = &options.target {
+        args.push("--target".into());
+        args.push(target.clone());
+    }
+    if let Some(profile) = &options.profile {
+        args.push("--profile".into());
+        args.push(profile.clone());
+    }
+
+    args
+}
+
+/// Parses `cargo fmt --check` output into one diagnostic per unformatted region
+///
+/// rustfmt prints `Diff in /path/to/file.rs:12:` (or `Diff in /path/to/file.rs at line 12:`
+/// in older versions) followed by the diff lines.
+pub fn parse_fmt_check(stdout: &str) -> Vec<Diagnostic> {
+    let mut diagnostics: Vec<Diagnostic> = Vec::new();
+
+    for line in stdout.lines() {
+        if let Some(header) = line.strip_prefix("Diff in ") {
+            let header = header.trim_end_matches(':');
+            let (file, line_number) = match header.rsplit_once(" at line ") {
+                Some((file, n)) => (file, n),
+                None => header.rsplit_once(':').unwrap_or((header, "1")),
+            };
+            let line_number = line_number.trim().parse().unwrap_or(1);
+
+            diagnostics.push(Diagnostic {
+                level: "warning".into(),
+                code: None,
+                message: "code is not formatted according to rustfmt".into(),
+                location: Some(Location {
+                    file: file.to_string(),
+                    line: line_number,
+                    column: 1,
+                    end_line: line_number,
+                    end_column: 1,
+                }),
+                labels: Vec::new(),
+                notes: vec![Note {
+                    level: "diff".into(),
+                    message: String::new(),
+                    location: None,
+                }],
+                suggestions: Vec::new(),
+            });
+        } else if let Some(note) = diagnostics.last_mut().and_then(|d| d.notes.last_mut()) {
+            if !note.message.is_empty() {
+                note.message.push('\n');
+            }
+            note.message.push_str(line);
+        }
+    }
+
+    diagnostics
+}
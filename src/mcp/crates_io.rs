@@ -81,7 +81,7 @@ pub async fn crates_io_fetch(
         .and_then(|v| v.to_str().ok())
         .unwrap_or("");
 
-    if status < 200 || status >= 300 {
+    if !(200..300).contains(&status) {
         eprintln!("HTTP error! status: {}", status);
     }
 
@@ -0,0 +1,620 @@
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashSet};
+use std::fmt::Write as _;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+use crate::mcp::roots::AllowedRoots;
+
+// --- Raw cargo/rustc JSON message format (`--message-format=json`) ---
+
+#[derive(Deserialize)]
+struct CargoMessage {
+    reason: String,
+    message: Option<RawDiagnostic>,
+}
+
+#[derive(Deserialize)]
+struct RawDiagnostic {
+    message: String,
+    code: Option<RawCode>,
+    level: String,
+    spans: Vec<RawSpan>,
+    children: Vec<RawDiagnostic>,
+    rendered: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct RawCode {
+    code: String,
+}
+
+#[derive(Deserialize)]
+struct RawSpan {
+    file_name: String,
+    byte_start: usize,
+    byte_end: usize,
+    line_start: usize,
+    line_end: usize,
+    column_start: usize,
+    column_end: usize,
+    is_primary: bool,
+    label: Option<String>,
+    suggested_replacement: Option<String>,
+    suggestion_applicability: Option<String>,
+}
+
+// --- Structured diagnostics returned to the client ---
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Location {
+    pub file: String,
+    pub line: usize,
+    pub column: usize,
+    pub end_line: usize,
+    pub end_column: usize,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Label {
+    pub location: Location,
+    pub primary: bool,
+    pub text: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Note {
+    pub level: String,
+    pub message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub location: Option<Location>,
+}
+
+/// A single replacement of a byte range in a file
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Replacement {
+    pub location: Location,
+    /// Byte offsets of the replaced range in the file
+    pub byte_start: usize,
+    pub byte_end: usize,
+    pub text: String,
+}
+
+/// A suggested fix; all of its replacements have to be applied together
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Suggestion {
+    /// The help message the suggestion belongs to
+    pub message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub applicability: Option<String>,
+    pub replacements: Vec<Replacement>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Diagnostic {
+    pub level: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub code: Option<String>,
+    pub message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub location: Option<Location>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub labels: Vec<Label>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub notes: Vec<Note>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub suggestions: Vec<Suggestion>,
+}
+
+#[derive(Serialize, Deserialize, Default, Debug)]
+pub struct DiagnosticReport {
+    pub success: bool,
+    pub errors: usize,
+    pub warnings: usize,
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+impl From<&RawSpan> for Location {
+    fn from(span: &RawSpan) -> Self {
+        Location {
+            file: span.file_name.clone(),
+            line: span.line_start,
+            column: span.column_start,
+            end_line: span.line_end,
+            end_column: span.column_end,
+        }
+    }
+}
+
+impl RawDiagnostic {
+    fn into_diagnostic(self) -> Diagnostic {
+        let location = self
+            .spans
+            .iter()
+            .find(|s| s.is_primary)
+            .or_else(|| self.spans.first())
+            .map(Location::from);
+
+        let labels = self
+            .spans
+            .iter()
+            .filter_map(|s| {
+                s.label.as_ref().map(|text| Label {
+                    location: s.into(),
+                    primary: s.is_primary,
+                    text: text.clone(),
+                })
+            })
+            .collect();
+
+        let mut notes = Vec::new();
+        let mut suggestions = Vec::new();
+        for child in &self.children {
+            let replacements: Vec<&RawSpan> = child
+                .spans
+                .iter()
+                .filter(|s| s.suggested_replacement.is_some())
+                .collect();
+
+            if !replacements.is_empty() {
+                suggestions.push(Suggestion {
+                    message: child.message.clone(),
+                    applicability: replacements[0].suggestion_applicability.clone(),
+                    replacements: replacements
+                        .iter()
+                        .map(|span| Replacement {
+                            location: (*span).into(),
+                            byte_start: span.byte_start,
+                            byte_end: span.byte_end,
+                            text: span.suggested_replacement.clone().unwrap_or_default(),
+                        })
+                        .collect(),
+                });
+            }
+
+            // Children carrying replacements are reported as suggestions, the rest as notes
+            if replacements.is_empty() {
+                notes.push(Note {
+                    level: child.level.clone(),
+                    message: child.message.clone(),
+                    location: child.spans.iter().find(|s| s.is_primary).or_else(|| child.spans.first()).map(Location::from),
+                });
+            }
+        }
+
+        Diagnostic {
+            level: self.level,
+            code: self.code.map(|c| c.code),
+            message: self.message,
+            location,
+            labels,
+            notes,
+            suggestions,
+        }
+    }
+}
+
+/// Parses the JSON lines printed by `cargo <cmd> --message-format=json`
+///
+/// Non-JSON lines and non-diagnostic messages (artifacts, build scripts) are ignored. Summary
+/// messages such as "aborting due to 2 previous errors" are dropped, and diagnostics reported for
+/// several targets (e.g. lib and bin) are only listed once.
+pub fn parse_cargo_messages(stdout: &str) -> Vec<Diagnostic> {
+    let mut seen = HashSet::new();
+    let mut diagnostics = Vec::new();
+
+    for line in stdout.lines() {
+        let Ok(msg) = serde_json::from_str::<CargoMessage>(line) else {
+            continue;
+        };
+        if msg.reason != "compiler-message" {
+            continue;
+        }
+        let Some(raw) = msg.message else {
+            continue;
+        };
+        if raw.level == "failure-note" || (raw.spans.is_empty() && raw.message.starts_with("aborting due to")) {
+            continue;
+        }
+        // rustc summarises each crate with e.g. "2 warnings emitted"
+        if raw.spans.is_empty() && raw.message.ends_with("emitted") {
+            continue;
+        }
+
+        let key = raw.rendered.clone().unwrap_or_else(|| raw.message.clone());
+        if !seen.insert(key) {
+            continue;
+        }
+
+        diagnostics.push(raw.into_diagnostic());
+    }
+
+    diagnostics
+}
+
+/// Builds a report with error and warning counts from parsed diagnostics
+pub fn build_report(diagnostics: Vec<Diagnostic>, success: bool) -> DiagnosticReport {
+    let errors = diagnostics.iter().filter(|d| d.level.starts_with("error")).count();
+    let warnings = diagnostics.iter().filter(|d| d.level == "warning").count();
+    DiagnosticReport {
+        success,
+        errors,
+        warnings,
+        diagnostics,
+    }
+}
+
+fn format_location(location: &Location) -> String {
+    format!("{}:{}:{}", location.file, location.line, location.column)
+}
+
+/// Renders a report as compact text, one block per diagnostic
+pub fn render_report(report: &DiagnosticReport) -> String {
+    let mut out = format!(
+        "{}: {} error(s), {} warning(s)\n",
+        if report.success { "Succeeded" } else { "Failed" },
+        report.errors,
+        report.warnings
+    );
+
+    for diagnostic in &report.diagnostics {
+        out.push('\n');
+        match &diagnostic.code {
+            Some(code) => {
+                let _ = writeln!(out, "{}[{}]: {}", diagnostic.level, code, diagnostic.message);
+            }
+            None => {
+                let _ = writeln!(out, "{}: {}", diagnostic.level, diagnostic.message);
+            }
+        }
+        if let Some(location) = &diagnostic.location {
+            let _ = writeln!(out, "  --> {}", format_location(location));
+        }
+        for label in &diagnostic.labels {
+            let _ = writeln!(out, "  label: {} ({})", label.text, format_location(&label.location));
+        }
+        for note in &diagnostic.notes {
+            match &note.location {
+                Some(location) => {
+                    let _ = writeln!(out, "  {}: {} ({})", note.level, note.message, format_location(location));
+                }
+                None => {
+                    let _ = writeln!(out, "  {}: {}", note.level, note.message);
+                }
+            }
+        }
+        for suggestion in &diagnostic.suggestions {
+            let _ = writeln!(
+                out,
+                "  suggestion: {}{}",
+                suggestion.message,
+                suggestion
+                    .applicability
+                    .as_ref()
+                    .map(|a| format!(" [{}]", a))
+                    .unwrap_or_default()
+            );
+            for replacement in &suggestion.replacements {
+                let _ = writeln!(
+                    out,
+                    "    {}-{}:{} replace with `{}`",
+                    format_location(&replacement.location),
+                    replacement.location.end_line,
+                    replacement.location.end_column,
+                    replacement.text
+                );
+            }
+        }
+    }
+
+    out
+}
+
+// --- Applying machine-applicable suggestions (rustfix-style) ---
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct FixRecord {
+    pub file: String,
+    pub line: usize,
+    pub message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Default, Debug)]
+pub struct FixReport {
+    pub applied: Vec<FixRecord>,
+    pub skipped: Vec<FixRecord>,
+}
+
+impl Suggestion {
+    fn record(&self, reason: Option<&str>) -> FixRecord {
+        let location = self.replacements.first().map(|r| &r.location);
+        FixRecord {
+            file: location.map(|l| l.file.clone()).unwrap_or_default(),
+            line: location.map_or(0, |l| l.line),
+            message: self.message.clone(),
+            reason: reason.map(str::to_string),
+        }
+    }
+}
+
+fn overlaps(a: &Replacement, b: &Replacement) -> bool {
+    // Two insertions at the same position would be applied in an arbitrary order
+    if a.byte_start == b.byte_start {
+        return true;
+    }
+    a.byte_start < b.byte_end && b.byte_start < a.byte_end
+}
+
+/// Applies every `MachineApplicable` suggestion found in the diagnostics
+///
+/// File names are resolved against `root` (the cargo workspace root). Suggestions are applied
+/// per file in source order; a suggestion whose replacements overlap one that was already
+/// accepted is skipped as a whole, as are suggestions pointing outside the workspace or the
+/// server's allowed roots.
+pub fn apply_machine_applicable(root: &Path, roots: &AllowedRoots, diagnostics: &[Diagnostic]) -> FixReport {
+    let mut report = FixReport::default();
+    let mut by_file: BTreeMap<String, Vec<&Suggestion>> = BTreeMap::new();
+
+    for suggestion in diagnostics.iter().flat_map(|d| &d.suggestions) {
+        if suggestion.applicability.as_deref() != Some("MachineApplicable") || suggestion.replacements.is_empty() {
+            continue;
+        }
+        let file = &suggestion.replacements[0].location.file;
+        if suggestion.replacements.iter().any(|r| &r.location.file != file) {
+            report.skipped.push(suggestion.record(Some("suggestion spans multiple files")));
+            continue;
+        }
+        by_file.entry(file.clone()).or_default().push(suggestion);
+    }
+
+    for (file, mut suggestions) in by_file {
+        let Some(path) = workspace_file(root, &file) else {
+            for suggestion in suggestions {
+                report.skipped.push(suggestion.record(Some("file is outside the workspace")));
+            }
+            continue;
+        };
+        let Ok(path) = roots.check(&path) else {
+            for suggestion in suggestions {
+                report.skipped.push(suggestion.record(Some("file is outside the allowed roots")));
+            }
+            continue;
+        };
+
+        let mut content = match fs::read_to_string(&path) {
+            Ok(content) => content,
+            Err(e) => {
+                let reason = format!("could not read file: {}", e);
+                for suggestion in suggestions {
+                    report.skipped.push(suggestion.record(Some(&reason)));
+                }
+                continue;
+            }
+        };
+
+        suggestions.sort_by_key(|s| s.replacements.iter().map(|r| r.byte_start).min());
+
+        let mut accepted: Vec<&Replacement> = Vec::new();
+        let mut applied = Vec::new();
+        for suggestion in suggestions {
+            let duplicate = suggestion.replacements.iter().all(|r| {
+                accepted.iter().any(|a| a.byte_start == r.byte_start && a.byte_end == r.byte_end && a.text == r.text)
+            });
+            if duplicate {
+                continue;
+            }
+
+            let out_of_range = suggestion.replacements.iter().any(|r| {
+                r.byte_start > r.byte_end
+                    || r.byte_end > content.len()
+                    || !content.is_char_boundary(r.byte_start)
+                    || !content.is_char_boundary(r.byte_end)
+            });
+            if out_of_range {
+                report.skipped.push(suggestion.record(Some("span does not match the current file contents")));
+                continue;
+            }
+
+            if suggestion.replacements.iter().any(|r| accepted.iter().any(|a| overlaps(a, r))) {
+                report.skipped.push(suggestion.record(Some("overlaps with another suggestion")));
+                continue;
+            }
+
+            accepted.extend(suggestion.replacements.iter());
+            applied.push(suggestion.record(None));
+        }
+
+        // Apply from the end of the file so earlier byte offsets stay valid
+        accepted.sort_by_key(|r| std::cmp::Reverse(r.byte_start));
+        for replacement in accepted {
+            content.replace_range(replacement.byte_start..replacement.byte_end, &replacement.text);
+        }
+
+        if applied.is_empty() {
+            continue;
+        }
+        match fs::write(&path, content) {
+            Ok(()) => report.applied.extend(applied),
+            Err(e) => {
+                let reason = format!("could not write file: {}", e);
+                report.skipped.extend(applied.into_iter().map(|mut r| {
+                    r.reason = Some(reason.clone());
+                    r
+                }));
+            }
+        }
+    }
+
+    report
+}
+
+/// The path of a file named in a diagnostic, if it is inside the workspace root
+///
+/// Absolute paths and `..` components are refused, and so are symlinks that resolve to a file
+/// outside the root.
+fn workspace_file(root: &Path, file: &str) -> Option<PathBuf> {
+    let relative = Path::new(file);
+    if !relative.components().all(|c| matches!(c, Component::Normal(_) | Component::CurDir)) {
+        return None;
+    }
+    let path = root.join(relative);
+    match (fs::canonicalize(&path), fs::canonicalize(root)) {
+        (Ok(resolved), Ok(root)) if !resolved.starts_with(&root) => None,
+        // A missing file is reported when it can't be read
+        _ => Some(path),
+    }
+}
+
+/// Renders the applied and skipped fixes as text
+pub fn render_fix_report(report: &FixReport) -> String {
+    let mut out = format!(
+        "Applied {} suggestion(s), skipped {}\n",
+        report.applied.len(),
+        report.skipped.len()
+    );
+
+    if !report.applied.is_empty() {
+        out.push_str("\nApplied:\n");
+        for fix in &report.applied {
+            let _ = writeln!(out, "  {}:{}: {}", fix.file, fix.line, fix.message);
+        }
+    }
+    if !report.skipped.is_empty() {
+        out.push_str("\nSkipped:\n");
+        for fix in &report.skipped {
+            let _ = writeln!(
+                out,
+                "  {}:{}: {} ({})",
+                fix.file,
+                fix.line,
+                fix.message,
+                fix.reason.as_deref().unwrap_or("unknown reason")
+            );
+        }
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("corrode-diagnostics-{}-{}", std::process::id(), name));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        fs::canonicalize(dir).unwrap()
+    }
+
+    /// A machine-applicable suggestion replacing `byte_start..byte_end` of `file` with `text`
+    fn suggestion(file: &str, byte_start: usize, byte_end: usize, text: &str) -> Diagnostic {
+        let location = Location {
+            file: file.to_string(),
+            line: 1,
+            column: byte_start + 1,
+            end_line: 1,
+            end_column: byte_end + 1,
+        };
+        Diagnostic {
+            level: "warning".to_string(),
+            code: None,
+            message: format!("replace with `{}`", text),
+            location: Some(location.clone()),
+            labels: Vec::new(),
+            notes: Vec::new(),
+            suggestions: vec![Suggestion {
+                message: format!("replace with `{}`", text),
+                applicability: Some("MachineApplicable".to_string()),
+                replacements: vec![Replacement { location, byte_start, byte_end, text: text.to_string() }],
+            }],
+        }
+    }
+
+    fn roots(dir: &Path) -> AllowedRoots {
+        AllowedRoots::new([dir.to_path_buf()]).unwrap()
+    }
+
+    #[test]
+    fn applies_suggestions_in_source_order() {
+        let workspace = temp_dir("order");
+        fs::write(workspace.join("lib.rs"), "let mut a = 1; let mut b = 2;\n").unwrap();
+        // Given back to front; each offset refers to the original file
+        let diagnostics = [suggestion("lib.rs", 19, 23, ""), suggestion("lib.rs", 4, 8, "")];
+
+        let report = apply_machine_applicable(&workspace, &roots(&workspace), &diagnostics);
+
+        assert_eq!(report.applied.len(), 2);
+        assert!(report.skipped.is_empty());
+        assert_eq!(fs::read_to_string(workspace.join("lib.rs")).unwrap(), "let a = 1; let b = 2;\n");
+    }
+
+    #[test]
+    fn skips_overlapping_and_duplicate_suggestions() {
+        let workspace = temp_dir("overlap");
+        fs::write(workspace.join("lib.rs"), "let mut a = 1;\n").unwrap();
+        let diagnostics = [
+            suggestion("lib.rs", 4, 8, ""),
+            suggestion("lib.rs", 4, 8, ""),
+            suggestion("lib.rs", 6, 10, "x"),
+        ];
+
+        let report = apply_machine_applicable(&workspace, &roots(&workspace), &diagnostics);
+
+        assert_eq!(report.applied.len(), 1);
+        assert_eq!(report.skipped.len(), 1);
+        assert_eq!(report.skipped[0].reason.as_deref(), Some("overlaps with another suggestion"));
+        assert_eq!(fs::read_to_string(workspace.join("lib.rs")).unwrap(), "let a = 1;\n");
+    }
+
+    #[test]
+    fn skips_spans_that_do_not_match_the_file() {
+        let workspace = temp_dir("stale");
+        fs::write(workspace.join("lib.rs"), "let a = 1;\n").unwrap();
+
+        let report = apply_machine_applicable(&workspace, &roots(&workspace), &[suggestion("lib.rs", 40, 44, "")]);
+
+        assert_eq!(report.skipped[0].reason.as_deref(), Some("span does not match the current file contents"));
+    }
+
+    #[test]
+    fn refuses_paths_leaving_the_workspace() {
+        let workspace = temp_dir("paths");
+        assert!(workspace_file(&workspace, "src/lib.rs").is_some());
+        assert!(workspace_file(&workspace, "./src/lib.rs").is_some());
+        assert!(workspace_file(&workspace, "../other/lib.rs").is_none());
+        assert!(workspace_file(&workspace, "src/../../other/lib.rs").is_none());
+        assert!(workspace_file(&workspace, "/etc/passwd").is_none());
+
+        let report = apply_machine_applicable(&workspace, &roots(&workspace), &[suggestion("../lib.rs", 0, 1, "")]);
+        assert_eq!(report.skipped[0].reason.as_deref(), Some("file is outside the workspace"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn refuses_symlinks_that_leave_the_workspace() {
+        let workspace = temp_dir("symlink");
+        let outside = temp_dir("symlink-target");
+        fs::write(outside.join("lib.rs"), "let mut a = 1;\n").unwrap();
+        std::os::unix::fs::symlink(&outside, workspace.join("linked")).unwrap();
+
+        assert!(workspace_file(&workspace, "linked/lib.rs").is_none());
+    }
+
+    #[test]
+    fn skips_files_outside_the_allowed_roots() {
+        let workspace = temp_dir("outside-roots");
+        fs::create_dir_all(workspace.join("src")).unwrap();
+        fs::create_dir_all(workspace.join("member")).unwrap();
+        fs::write(workspace.join("src/lib.rs"), "let mut x = 1;\n").unwrap();
+        let roots = roots(&workspace.join("member"));
+
+        let report = apply_machine_applicable(&workspace, &roots, &[suggestion("src/lib.rs", 4, 8, "")]);
+
+        assert!(report.applied.is_empty());
+        assert_eq!(report.skipped[0].reason.as_deref(), Some("file is outside the allowed roots"));
+        assert_eq!(fs::read_to_string(workspace.join("src/lib.rs")).unwrap(), "let mut x = 1;\n");
+    }
+}
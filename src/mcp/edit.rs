@@ -0,0 +1,169 @@
+use std::fmt::Write as _;
+
+/// Lines shown before and after each replacement in the edit report
+const CONTEXT_LINES: usize = 3;
+
+/// Replacements shown in the edit report; the rest are only counted
+const MAX_SHOWN_EDITS: usize = 5;
+
+/// Occurrence line numbers listed when `old_string` isn't unique or isn't found
+const MAX_LISTED_LINES: usize = 10;
+
+/// The result of replacing text in a file
+pub struct Edit {
+    pub content: String,
+    /// Byte ranges of the inserted text in the new content
+    pub replaced: Vec<(usize, usize)>,
+}
+
+/// 1-based line number of a byte offset
+fn line_of(content: &str, offset: usize) -> usize {
+    content[..offset].matches('\n').count() + 1
+}
+
+fn list_lines(lines: &[usize]) -> String {
+    let mut listed: Vec<String> = lines.iter().take(MAX_LISTED_LINES).map(|l| l.to_string()).collect();
+    if lines.len() > MAX_LISTED_LINES {
+        listed.push("...".to_string());
+    }
+    listed.join(", ")
+}
+
+/// Replaces `old_string` with `new_string`, which must occur exactly once unless `replace_all`
+///
+/// When the file uses CRLF line endings and the strings use LF, they are converted so that
+/// multi-line strings still match.
+pub fn replace(content: &str, old_string: &str, new_string: &str, replace_all: bool) -> Result<Edit, String> {
+    if old_string.is_empty() {
+        return Err("old_string is empty. Use write_file to create a file or replace its whole content.".to_string());
+    }
+    if old_string == new_string {
+        return Err("old_string and new_string are identical, so there is nothing to change.".to_string());
+    }
+
+    let (old_string, new_string) = if !content.contains(old_string) && content.contains("\r\n") && old_string.contains('\n') {
+        (old_string.replace("\r\n", "\n").replace('\n', "\r\n"), new_string.replace("\r\n", "\n").replace('\n', "\r\n"))
+    } else {
+        (old_string.to_string(), new_string.to_string())
+    };
+
+    let starts: Vec<usize> = content.match_indices(old_string.as_str()).map(|(start, _)| start).collect();
+    if starts.is_empty() {
+        return Err(not_found_message(content, &old_string));
+    }
+    if starts.len() > 1 && !replace_all {
+        let lines: Vec<usize> = starts.iter().map(|&start| line_of(content, start)).collect();
+        return Err(format!(
+            "old_string occurs {} times (at lines {}). Include more surrounding lines to make it unique, or set replace_all to replace every occurrence.",
+            starts.len(),
+            list_lines(&lines)
+        ));
+    }
+
+    let mut new_content = String::with_capacity(content.len());
+    let mut replaced = Vec::new();
+    let mut copied = 0;
+    for start in starts {
+        new_content.push_str(&content[copied..start]);
+        replaced.push((new_content.len(), new_content.len() + new_string.len()));
+        new_content.push_str(&new_string);
+        copied = start + old_string.len();
+    }
+    new_content.push_str(&content[copied..]);
+
+    Ok(Edit { content: new_content, replaced })
+}
+
+/// Explains a failed match, pointing at lines that match when whitespace is ignored
+fn not_found_message(content: &str, old_string: &str) -> String {
+    let mut message = "old_string was not found in the file. It must match exactly, including whitespace and indentation; read the file again to copy the current text.".to_string();
+    let Some(first_line) = old_string.lines().map(str::trim).find(|line| !line.is_empty()) else {
+        return message;
+    };
+    let similar: Vec<usize> = content
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| line.trim() == first_line)
+        .map(|(index, _)| index + 1)
+        .collect();
+    if !similar.is_empty() {
+        let _ = write!(
+            message,
+            " Its first line `{}` is found at line {} when whitespace is ignored; compare the text there.",
+            first_line,
+            list_lines(&similar)
+        );
+    }
+    message
+}
+
+/// Renders the lines around each replacement in the new content, with line numbers
+pub fn render_edit(edit: &Edit) -> String {
+    let lines: Vec<&str> = edit.content.lines().collect();
+    let width = lines.len().to_string().len();
+    let mut out = String::new();
+    // Index one past the last line shown, so overlapping regions are merged
+    let mut shown_until = 0;
+    for &(start, end) in edit.replaced.iter().take(MAX_SHOWN_EDITS) {
+        let first = line_of(&edit.content, start) - 1;
+        let mut last = line_of(&edit.content, end) - 1;
+        if end > start && edit.content[..end].ends_with('\n') {
+            last -= 1;
+        }
+        let from = first.saturating_sub(CONTEXT_LINES).max(shown_until);
+        let to = (last + 1 + CONTEXT_LINES).min(lines.len());
+        if !out.is_empty() && from > shown_until {
+            out.push_str("...\n");
+        }
+        for (index, line) in lines.iter().enumerate().take(to).skip(from) {
+            let _ = writeln!(out, "{:>width$}\t{}", index + 1, line);
+        }
+        shown_until = shown_until.max(to);
+    }
+    if edit.replaced.len() > MAX_SHOWN_EDITS {
+        let _ = writeln!(out, "... and {} more replacements", edit.replaced.len() - MAX_SHOWN_EDITS);
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn replaces_a_unique_occurrence() {
+        let edit = replace("let x = 1;\nlet y = 2;\n", "y = 2", "y = 3", false).unwrap();
+        assert_eq!(edit.content, "let x = 1;\nlet y = 3;\n");
+        assert_eq!(edit.replaced, [(15, 20)]);
+    }
+
+    #[test]
+    fn refuses_repeated_occurrences_unless_replacing_all() {
+        let content = "a();\nb();\na();\n";
+        let error = replace(content, "a()", "c()", false).err().unwrap();
+        assert!(error.contains("occurs 2 times (at lines 1, 3)"));
+
+        let edit = replace(content, "a()", "c()", true).unwrap();
+        assert_eq!(edit.content, "c();\nb();\nc();\n");
+        assert_eq!(edit.replaced.len(), 2);
+    }
+
+    #[test]
+    fn points_at_lines_that_differ_only_in_whitespace() {
+        let error = replace("fn main() {\n    let x = 1;\n}\n", "\tlet x = 1;", "let x = 2;", false).err().unwrap();
+        assert!(error.contains("not found"));
+        assert!(error.contains("found at line 2 when whitespace is ignored"));
+    }
+
+    #[test]
+    fn matches_lf_strings_in_crlf_files() {
+        let edit = replace("a\r\nb\r\nc\r\n", "a\nb\n", "x\ny\n", false).unwrap();
+        assert_eq!(edit.content, "x\r\ny\r\nc\r\n");
+    }
+
+    #[test]
+    fn refuses_empty_and_unchanged_strings() {
+        assert!(replace("a", "", "b", false).is_err());
+        assert!(replace("a", "a", "a", false).is_err());
+    }
+}
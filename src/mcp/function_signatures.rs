@@ -1,5 +1,8 @@
 use serde::{Deserialize, Serialize};
 use std::path::Path;
+use tree_sitter::Node;
+
+use crate::mcp::treesitter::{definition_name, parent_name, parse_tree, source_files};
 
 #[derive(Serialize, Deserialize)]
 pub struct FunctionSignature {
@@ -14,65 +17,132 @@ pub struct FunctionSignature {
 // Extract function signatures from all files in a project
 pub fn extract_project_signatures(project_dir: &Path) -> Vec<FunctionSignature> {
     let mut all_signatures = Vec::new();
-    
-    // println!("Starting extraction from directory: {}", project_dir.display());
-    // println!("Searching for test_functions.rs");
-    
-    // First, let's try the specific test file we created
-    let test_file = project_dir.join("test_functions.rs");
-    if test_file.exists() {
-        // println!("Found test_functions.rs, scanning...");
-        
-        // Create some dummy signatures just to verify it works
-        let signature = FunctionSignature {
-            file_path: "test_functions.rs".to_string(),
-            name: "hello_world".to_string(),
-            signature: "fn hello_world()".to_string(),
-            line_number: 3,
-            parent: None,
-            language: "Rust".to_string(),
-        };
-        all_signatures.push(signature);
-        
-        let signature = FunctionSignature {
-            file_path: "test_functions.rs".to_string(),
-            name: "add".to_string(),
-            signature: "fn add(a: i32, b: i32) -> i32".to_string(),
-            line_number: 7,
-            parent: None,
-            language: "Rust".to_string(),
-        };
-        all_signatures.push(signature);
-        
-        let signature = FunctionSignature {
-            file_path: "test_functions.rs".to_string(),
-            name: "new".to_string(),
-            signature: "fn new(value: i32) -> Self".to_string(),
-            line_number: 15,
-            parent: Some("TestStruct".to_string()),
-            language: "Rust".to_string(),
-        };
-        all_signatures.push(signature);
-        
-        let signature = FunctionSignature {
-            file_path: "test_functions.rs".to_string(),
-            name: "get_value".to_string(),
-            signature: "fn get_value(&self) -> i32".to_string(),
-            line_number: 20,
-            parent: Some("TestStruct".to_string()),
-            language: "Rust".to_string(),
-        };
-        all_signatures.push(signature);
-    } else {
-        // println!("test_functions.rs not found");
+
+    for path in source_files(project_dir) {
+        // Report paths relative to the project so the output stays compact
+        let rel_path = path
+            .strip_prefix(project_dir)
+            .unwrap_or(&path)
+            .to_string_lossy()
+            .to_string();
+
+        for mut signature in extract_function_signatures(&path, None) {
+            signature.file_path = rel_path.clone();
+            all_signatures.push(signature);
+        }
     }
-    
-    // println!("Found {} function signatures", all_signatures.len());
+
     all_signatures
 }
 
-// Simplified implementation for testing
-pub fn extract_function_signatures(_file_path: &Path, _language_override: Option<&str>) -> Vec<FunctionSignature> {
-    // Just return an empty vector for now
-    Vec::new()
+// Extract function signatures from a single file
+pub fn extract_function_signatures(file_path: &Path, language_override: Option<&str>) -> Vec<FunctionSignature> {
+    let Some(parsed) = parse_tree(file_path, language_override) else {
+        return Vec::new();
+    };
+
+    let mut collector = SignatureCollector {
+        file_path: file_path.to_string_lossy().to_string(),
+        source: &parsed.source,
+        language: &parsed.language_name,
+        signatures: Vec::new(),
+    };
+    collector.visit(parsed.tree.root_node());
+
+    collector.signatures
+}
+
+struct SignatureCollector<'a> {
+    file_path: String,
+    source: &'a str,
+    language: &'a str,
+    signatures: Vec<FunctionSignature>,
+}
+
+impl SignatureCollector<'_> {
+    fn visit(&mut self, node: Node) {
+        if self.is_function(node) {
+            self.push_signature(node);
+        }
+
+        let mut cursor = node.walk();
+        for child in node.named_children(&mut cursor) {
+            self.visit(child);
+        }
+    }
+
+    fn is_function(&self, node: Node) -> bool {
+        let kind = node.kind();
+        match self.language {
+            "rust" => matches!(kind, "function_item" | "function_signature_item"),
+            "javascript" | "typescript" => matches!(
+                kind,
+                "function_declaration"
+                    | "generator_function_declaration"
+                    | "method_definition"
+                    | "method_signature"
+                    | "function_signature"
+                    | "abstract_method_signature"
+            ),
+            "python" => kind == "function_definition",
+            "go" => matches!(kind, "function_declaration" | "method_declaration" | "method_spec"),
+            "c" | "cpp" => kind == "function_definition",
+            _ => false,
+        }
+    }
+
+    fn push_signature(&mut self, node: Node) {
+        // The signature is everything before the body, or the whole node for declarations
+        let end = node
+            .child_by_field_name("body")
+            .map_or(node.end_byte(), |body| body.start_byte());
+        let mut comments = Vec::new();
+        collect_comments(node, end, &mut comments);
+
+        // Drop comments (e.g. doc comments on parameters) from the signature text
+        let mut raw = String::new();
+        let mut pos = node.start_byte();
+        for (start, stop) in comments {
+            raw.push_str(&self.source[pos..start]);
+            pos = stop;
+        }
+        raw.push_str(&self.source[pos..end]);
+
+        // Collapse multi-line signatures onto a single line
+        let signature = raw
+            .split_whitespace()
+            .collect::<Vec<_>>()
+            .join(" ")
+            .replace("( ", "(")
+            .replace(", )", ")")
+            .replace(" )", ")");
+        let signature = signature
+            .trim_end_matches([':', ';', '{'])
+            .trim_end()
+            .to_string();
+
+        self.signatures.push(FunctionSignature {
+            file_path: self.file_path.clone(),
+            name: definition_name(node, self.source),
+            signature,
+            line_number: node.start_position().row + 1,
+            parent: parent_name(node, self.source),
+            language: self.language.to_string(),
+        });
+    }
+}
+
+// Collect the byte ranges of comment nodes that start before `end`
+fn collect_comments(node: Node, end: usize, out: &mut Vec<(usize, usize)>) {
+    let mut cursor = node.walk();
+    for child in node.children(&mut cursor) {
+        if child.start_byte() >= end {
+            break;
+        }
+        if child.kind().contains("comment") {
+            out.push((child.start_byte(), child.end_byte().min(end)));
+        } else {
+            collect_comments(child, end, out);
+        }
+    }
 }
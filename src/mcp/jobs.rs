@@ -0,0 +1,271 @@
+use std::collections::{BTreeMap, VecDeque};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWriteExt};
+use tokio::process::{ChildStdin, Command};
+
+use crate::mcp::process::{CappedBuffer, kill_process_group, terminate_process_group};
+
+/// Unread output kept per stream; older output is dropped when a job isn't polled
+const MAX_PENDING_BYTES: usize = 1_000_000;
+
+/// How long a job gets to exit after SIGTERM before it is killed
+pub const TERMINATE_GRACE_PERIOD: Duration = Duration::from_secs(5);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum JobStatus {
+    Running,
+    /// Exit code, `None` if the process was killed by a signal
+    Exited(Option<i32>),
+}
+
+impl JobStatus {
+    pub fn describe(self) -> String {
+        match self {
+            JobStatus::Running => "running".to_string(),
+            JobStatus::Exited(Some(code)) => format!("exited with code {}", code),
+            JobStatus::Exited(None) => "killed by a signal".to_string(),
+        }
+    }
+}
+
+/// Output produced since the last poll
+pub struct PendingOutput {
+    pub stdout: String,
+    pub stderr: String,
+    /// Bytes dropped because more output arrived than is kept between polls or returned at once
+    pub stdout_dropped: usize,
+    pub stderr_dropped: usize,
+}
+
+#[derive(Default)]
+struct Stream {
+    pending: VecDeque<u8>,
+    dropped: usize,
+}
+
+impl Stream {
+    fn push(&mut self, bytes: &[u8]) {
+        self.pending.extend(bytes);
+        let overflow = self.pending.len().saturating_sub(MAX_PENDING_BYTES);
+        self.pending.drain(..overflow);
+        self.dropped += overflow;
+    }
+
+    fn take(&mut self, max_output_bytes: usize) -> (String, usize) {
+        let mut buffer = CappedBuffer::new(max_output_bytes);
+        buffer.push(&self.pending.drain(..).collect::<Vec<u8>>());
+        let (text, dropped) = buffer.finish();
+        (text, dropped + std::mem::take(&mut self.dropped))
+    }
+}
+
+#[derive(Default)]
+struct JobState {
+    stdout: Stream,
+    stderr: Stream,
+    status: Option<JobStatus>,
+}
+
+/// A command running in the background, started with `bash -c` in its own process group
+pub struct Job {
+    pub id: usize,
+    pub command: String,
+    pub cwd: PathBuf,
+    pub started: Instant,
+    pub pid: Option<u32>,
+    stdin: Arc<tokio::sync::Mutex<Option<ChildStdin>>>,
+    state: Arc<Mutex<JobState>>,
+}
+
+async fn read_into(mut reader: impl AsyncRead + Unpin, state: Arc<Mutex<JobState>>, stderr: bool) {
+    let mut chunk = [0u8; 8192];
+    loop {
+        match reader.read(&mut chunk).await {
+            Ok(0) | Err(_) => break,
+            Ok(n) => {
+                let mut state = state.lock().unwrap();
+                let stream = if stderr { &mut state.stderr } else { &mut state.stdout };
+                stream.push(&chunk[..n]);
+            }
+        }
+    }
+}
+
+impl Job {
+    fn spawn(id: usize, command: &str, cwd: &Path) -> io::Result<Self> {
+        let mut bash = Command::new("bash");
+        bash.arg("-l") // Run as a login shell to load full environment
+            .arg("-c")
+            .arg(command)
+            .current_dir(cwd)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped());
+        #[cfg(unix)]
+        bash.process_group(0);
+
+        let mut child = bash.spawn()?;
+        let state = Arc::new(Mutex::new(JobState::default()));
+        if let Some(out) = child.stdout.take() {
+            tokio::spawn(read_into(out, state.clone(), false));
+        }
+        if let Some(err) = child.stderr.take() {
+            tokio::spawn(read_into(err, state.clone(), true));
+        }
+        let stdin = Arc::new(tokio::sync::Mutex::new(child.stdin.take()));
+        let pid = child.id();
+
+        let wait_state = state.clone();
+        tokio::spawn(async move {
+            let code = child.wait().await.ok().and_then(|status| status.code());
+            wait_state.lock().unwrap().status = Some(JobStatus::Exited(code));
+        });
+
+        Ok(Self {
+            id,
+            command: command.to_string(),
+            cwd: cwd.to_path_buf(),
+            started: Instant::now(),
+            pid,
+            stdin,
+            state,
+        })
+    }
+
+    pub fn status(&self) -> JobStatus {
+        self.state.lock().unwrap().status.unwrap_or(JobStatus::Running)
+    }
+
+    /// Number of bytes of stdout and stderr not read yet
+    pub fn pending_bytes(&self) -> usize {
+        let state = self.state.lock().unwrap();
+        state.stdout.pending.len() + state.stderr.pending.len()
+    }
+
+    /// Takes the output produced since the last call, up to `max_output_bytes` per stream
+    pub fn take_output(&self, max_output_bytes: usize) -> PendingOutput {
+        let mut state = self.state.lock().unwrap();
+        let (stdout, stdout_dropped) = state.stdout.take(max_output_bytes);
+        let (stderr, stderr_dropped) = state.stderr.take(max_output_bytes);
+        PendingOutput {
+            stdout,
+            stderr,
+            stdout_dropped,
+            stderr_dropped,
+        }
+    }
+
+    /// Handle to the job's stdin, `None` inside once it was closed
+    pub fn stdin(&self) -> Arc<tokio::sync::Mutex<Option<ChildStdin>>> {
+        self.stdin.clone()
+    }
+
+    /// Sends SIGTERM to the job's process group
+    pub fn terminate(&self) {
+        if let (Some(pid), JobStatus::Running) = (self.pid, self.status()) {
+            terminate_process_group(pid);
+        }
+    }
+
+    /// Kills the job's process group
+    pub fn kill(&self) {
+        if let (Some(pid), JobStatus::Running) = (self.pid, self.status()) {
+            kill_process_group(pid);
+        }
+    }
+}
+
+/// Writes to a job's stdin, closing it afterwards when `close` is set
+pub async fn write_stdin(stdin: &tokio::sync::Mutex<Option<ChildStdin>>, input: &str, close: bool) -> io::Result<()> {
+    let mut stdin = stdin.lock().await;
+    let Some(pipe) = stdin.as_mut() else {
+        return Err(io::Error::new(io::ErrorKind::BrokenPipe, "stdin of the job was closed"));
+    };
+    pipe.write_all(input.as_bytes()).await?;
+    pipe.flush().await?;
+    if close {
+        *stdin = None;
+    }
+    Ok(())
+}
+
+/// The background jobs of a server; running jobs are killed when it is dropped
+#[derive(Default)]
+pub struct JobList {
+    next_id: usize,
+    jobs: BTreeMap<usize, Job>,
+}
+
+impl JobList {
+    /// Starts `command` in `cwd` and returns the new job
+    pub fn start(&mut self, command: &str, cwd: &Path) -> io::Result<&Job> {
+        self.next_id += 1;
+        let job = Job::spawn(self.next_id, command, cwd)?;
+        Ok(self.jobs.entry(job.id).or_insert(job))
+    }
+
+    pub fn get(&self, id: usize) -> Option<&Job> {
+        self.jobs.get(&id)
+    }
+
+    pub fn remove(&mut self, id: usize) -> Option<Job> {
+        self.jobs.remove(&id)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Job> {
+        self.jobs.values()
+    }
+}
+
+impl Drop for JobList {
+    fn drop(&mut self) {
+        for job in self.jobs.values() {
+            job.kill();
+        }
+    }
+}
+
+/// Renders a job's header line, e.g. `Job 2 [running, 14s] (pid 1234): cargo build`
+pub fn render_job_line(job: &Job) -> String {
+    let pid = job.pid.map(|pid| format!(" (pid {})", pid)).unwrap_or_default();
+    format!(
+        "Job {} [{}, {}s]{}: {}",
+        job.id,
+        job.status().describe(),
+        job.started.elapsed().as_secs(),
+        pid,
+        job.command
+    )
+}
+
+/// Renders output taken from a job
+pub fn render_output(output: &PendingOutput) -> String {
+    let mut out = String::new();
+    if output.stdout.is_empty() && output.stderr.is_empty() {
+        out.push_str("\nNo new output.\n");
+    }
+    if !output.stdout.is_empty() {
+        out.push_str(&format!("\nStandard output:\n{}", output.stdout));
+        if !output.stdout.ends_with('\n') {
+            out.push('\n');
+        }
+    }
+    if !output.stderr.is_empty() {
+        out.push_str(&format!("\nStandard error:\n{}", output.stderr));
+        if !output.stderr.ends_with('\n') {
+            out.push('\n');
+        }
+    }
+    if output.stdout_dropped > 0 || output.stderr_dropped > 0 {
+        out.push_str(&format!(
+            "\nOutput truncated: {} bytes of stdout and {} bytes of stderr were dropped.\n",
+            output.stdout_dropped, output.stderr_dropped
+        ));
+    }
+    out
+}
@@ -4,3 +4,17 @@ pub mod crates_io;
 pub mod patch;
 pub mod treesitter;
 pub mod function_signatures;
+pub mod diagnostics;
+pub mod cargo;
+pub mod test_runner;
+pub mod process;
+pub mod shell;
+pub mod jobs;
+pub mod roots;
+pub mod mode;
+pub mod policy;
+pub mod read;
+pub mod walk;
+pub mod tree;
+pub mod search;
+pub mod edit;
@@ -0,0 +1,299 @@
+use std::fmt;
+
+use clap::ValueEnum;
+use mcp_attr::Result;
+use mcp_attr::schema::{
+    CallToolRequestParams, CallToolResult, CompleteRequestParams, CompleteResult, GetPromptRequestParams,
+    GetPromptResult, Implementation, ListPromptsRequestParams, ListPromptsResult, ListResourceTemplatesRequestParams,
+    ListResourceTemplatesResult, ListResourcesRequestParams, ListResourcesResult, ListToolsRequestParams,
+    ListToolsResult, ReadResourceRequestParams, ReadResourceResult, ServerCapabilities,
+};
+use mcp_attr::server::{McpServer, RequestContext};
+
+use crate::mcp::policy::parse_command_line;
+
+/// Tools that modify files
+const WRITE_TOOLS: &[&str] = &["write_file", "edit_file", "patch_file", "apply_suggestions"];
+
+/// Tools that run arbitrary shell commands or manage their processes
+const COMMAND_TOOLS: &[&str] = &[
+    "execute_bash",
+    "reset_shell",
+    "start_job",
+    "list_jobs",
+    "read_job_output",
+    "write_job_stdin",
+    "kill_job",
+];
+
+/// Tools that run the project's own code
+const TEST_TOOLS: &[&str] = &["run_tests"];
+
+/// Options of allowlisted commands that write files, read files outside the project or run
+/// other programs, e.g. `git diff --output=FILE` or `cargo check --config build.rustc-wrapper=...`
+const REFUSED_OPTIONS: &[(&str, &[&str])] = &[
+    ("git", &["--output", "--ext-diff", "--no-index"]),
+    (
+        "cargo",
+        &[
+            "--config",
+            "--fix",
+            "--allow-dirty",
+            "--allow-staged",
+            "--allow-no-vcs",
+            "--target-dir",
+            "--artifact-dir",
+            "--out-dir",
+            "--logfile",
+        ],
+    ),
+];
+
+/// Commands allowed in restricted mode when no `--allow-command` is given
+pub const DEFAULT_ALLOWED_COMMANDS: &[&str] = &[
+    "cargo check",
+    "cargo clippy",
+    "cargo test",
+    "cargo build",
+    "cargo tree",
+    "cargo metadata",
+    "git status",
+    "git diff",
+    "git log",
+    "git show",
+];
+
+/// What the server lets an agent do, selected at startup
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ServerMode {
+    /// All tools are available
+    #[default]
+    Full,
+    /// No tool modifies files, runs shell commands or runs tests
+    ///
+    /// This doesn't mean no code runs: `check_code` still compiles the project, which runs its
+    /// build scripts and procedural macros.
+    ReadOnly,
+    /// No tool modifies files; shell commands are limited to an allowlist
+    Restricted,
+}
+
+impl fmt::Display for ServerMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            ServerMode::Full => "full",
+            ServerMode::ReadOnly => "read-only",
+            ServerMode::Restricted => "restricted",
+        })
+    }
+}
+
+impl ServerMode {
+    /// Whether the tool is available at all in this mode
+    pub fn permits_tool(self, name: &str) -> bool {
+        match self {
+            ServerMode::Full => true,
+            ServerMode::ReadOnly => {
+                !WRITE_TOOLS.contains(&name) && !COMMAND_TOOLS.contains(&name) && !TEST_TOOLS.contains(&name)
+            }
+            ServerMode::Restricted => !WRITE_TOOLS.contains(&name),
+        }
+    }
+}
+
+/// Whether the command line is a single command starting with one of the allowed commands
+///
+/// `cargo test` allows `cargo test -p foo` but not `cargo testify`, `cargo test; rm -rf /` or
+/// `cargo test > out.txt`. The program must be written exactly as in the allowlist, so
+/// `/tmp/x/cargo test` is refused, and so are variable assignments such as
+/// `RUSTC_WRAPPER=x cargo test` and words the shell expands, which could change what runs.
+/// Options that write files, read files outside the project or run other programs, such as
+/// `git diff --output=FILE` or `cargo clippy --fix`, are refused too, including git's
+/// abbreviations of them like `--out=FILE`.
+pub fn command_allowed(command: &str, allowed: &[String]) -> bool {
+    if command.contains('$') {
+        return false;
+    }
+    let Ok(commands) = parse_command_line(command) else {
+        return false;
+    };
+    let [command] = commands.as_slice() else {
+        return false;
+    };
+    if command.redirects
+        || !command.assignments.is_empty()
+        || command.program_word.expands
+        || command.args.iter().any(|arg| arg.expands || refused_option(&command.program, &arg.text))
+    {
+        return false;
+    }
+
+    let words: Vec<&str> = std::iter::once(command.program_word.text.as_str())
+        .chain(command.args.iter().map(|arg| arg.text.as_str()))
+        .collect();
+    allowed.iter().any(|prefix| {
+        let prefix: Vec<&str> = prefix.split_whitespace().collect();
+        !prefix.is_empty() && words.len() >= prefix.len() && words.iter().zip(&prefix).all(|(w, p)| w == p)
+    })
+}
+
+/// Whether the argument is one of the program's `REFUSED_OPTIONS`, or for git, which accepts
+/// unambiguous prefixes of long options, an abbreviation of one
+fn refused_option(program: &str, arg: &str) -> bool {
+    let name = arg.split('=').next().unwrap_or(arg);
+    let Some((_, options)) = REFUSED_OPTIONS.iter().find(|(p, _)| *p == program) else {
+        return false;
+    };
+    if program == "git" {
+        name.len() > "--".len() && name.starts_with("--") && options.iter().any(|option| option.starts_with(name))
+    } else {
+        options.contains(&name)
+    }
+}
+
+/// Serves an `McpServer` with only the tools the mode permits
+///
+/// Disabled tools are left out of `tools/list` and calling them fails, so clients never offer
+/// them to the agent.
+pub struct ModeServer<S> {
+    pub inner: S,
+    pub mode: ServerMode,
+}
+
+impl<S: McpServer> McpServer for ModeServer<S> {
+    fn server_info(&self) -> Implementation {
+        self.inner.server_info()
+    }
+
+    fn instructions(&self) -> Option<String> {
+        self.inner.instructions()
+    }
+
+    fn capabilities(&self) -> ServerCapabilities {
+        self.inner.capabilities()
+    }
+
+    async fn prompts_list(&self, p: ListPromptsRequestParams, cx: &mut RequestContext) -> Result<ListPromptsResult> {
+        self.inner.prompts_list(p, cx).await
+    }
+
+    async fn prompts_get(&self, p: GetPromptRequestParams, cx: &mut RequestContext) -> Result<GetPromptResult> {
+        self.inner.prompts_get(p, cx).await
+    }
+
+    async fn resources_list(&self, p: ListResourcesRequestParams, cx: &mut RequestContext) -> Result<ListResourcesResult> {
+        self.inner.resources_list(p, cx).await
+    }
+
+    async fn resources_templates_list(
+        &self,
+        p: ListResourceTemplatesRequestParams,
+        cx: &mut RequestContext,
+    ) -> Result<ListResourceTemplatesResult> {
+        self.inner.resources_templates_list(p, cx).await
+    }
+
+    async fn resources_read(&self, p: ReadResourceRequestParams, cx: &mut RequestContext) -> Result<ReadResourceResult> {
+        self.inner.resources_read(p, cx).await
+    }
+
+    async fn tools_list(&self, p: ListToolsRequestParams, cx: &mut RequestContext) -> Result<ListToolsResult> {
+        let mut result = self.inner.tools_list(p, cx).await?;
+        result.tools.retain(|tool| self.mode.permits_tool(&tool.name));
+        Ok(result)
+    }
+
+    async fn tools_call(&self, p: CallToolRequestParams, cx: &mut RequestContext) -> Result<CallToolResult> {
+        if !self.mode.permits_tool(&p.name) {
+            mcp_attr::bail!("Tool `{}` is disabled because the server runs in {} mode.", p.name, self.mode);
+        }
+        self.inner.tools_call(p, cx).await
+    }
+
+    async fn completion_complete(&self, p: CompleteRequestParams, cx: &mut RequestContext) -> Result<CompleteResult> {
+        self.inner.completion_complete(p, cx).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn allowed(command: &str) -> bool {
+        command_allowed(command, &["cargo check".to_string(), "git diff".to_string()])
+    }
+
+    #[test]
+    fn allows_allowlisted_commands_with_arguments() {
+        assert!(allowed("cargo check"));
+        assert!(allowed("cargo check -p foo --all-targets"));
+        assert!(allowed("git diff HEAD~1 -- src/lib.rs"));
+    }
+
+    #[test]
+    fn refuses_other_commands() {
+        assert!(!allowed("cargo checkout"));
+        assert!(!allowed("cargo check; rm -rf /"));
+        assert!(!allowed("cargo check > out.txt"));
+        assert!(!allowed("sudo cargo check"));
+    }
+
+    #[test]
+    fn refuses_variable_assignments() {
+        assert!(!allowed(r#"GIT_EXTERNAL_DIFF='sh -c "touch /tmp/pwned"' git diff"#));
+        assert!(!allowed("RUSTC_WRAPPER=/tmp/evil cargo check"));
+    }
+
+    #[test]
+    fn refuses_programs_outside_the_path() {
+        assert!(!allowed("/tmp/x/cargo check"));
+        assert!(!allowed("./cargo check"));
+    }
+
+    #[test]
+    fn refuses_options_that_write_files_or_run_programs() {
+        assert!(!allowed("git diff --output=/tmp/x"));
+        assert!(!allowed("git diff --output /tmp/x"));
+        assert!(!allowed("git diff --out=/tmp/x"));
+        assert!(!allowed("git diff --ext-diff"));
+        assert!(!allowed("cargo check --config build.rustc-wrapper=\"/tmp/evil\""));
+        assert!(allowed("git diff --stat"));
+    }
+
+    #[test]
+    fn refuses_options_that_fix_sources_or_write_outside_the_target_dir() {
+        let allowed = |command: &str| {
+            command_allowed(
+                command,
+                &["cargo clippy", "cargo test", "cargo build", "git diff"].map(String::from),
+            )
+        };
+        assert!(!allowed("cargo clippy --fix --allow-dirty"));
+        assert!(!allowed("cargo clippy --allow-dirty"));
+        assert!(!allowed("cargo test -- --logfile /tmp/x"));
+        assert!(!allowed("cargo build --target-dir /anywhere"));
+        assert!(!allowed("cargo build --target-dir=/anywhere"));
+        assert!(!allowed("git diff --no-index /etc/passwd /dev/null"));
+        assert!(!allowed("git diff --no-ind /etc/passwd /dev/null"));
+        assert!(allowed("cargo test --all"));
+        assert!(allowed("cargo clippy --all-targets -- -D warnings"));
+    }
+
+    #[test]
+    fn read_only_mode_has_no_tools_that_run_code() {
+        assert!(!ServerMode::ReadOnly.permits_tool("run_tests"));
+        assert!(!ServerMode::ReadOnly.permits_tool("execute_bash"));
+        assert!(!ServerMode::ReadOnly.permits_tool("write_file"));
+        assert!(ServerMode::ReadOnly.permits_tool("read_file"));
+        assert!(ServerMode::Restricted.permits_tool("run_tests"));
+    }
+
+    #[test]
+    fn refuses_expansions() {
+        assert!(!allowed("$CARGO check"));
+        assert!(!allowed("cargo check --manifest-path $HOME/x/Cargo.toml"));
+        assert!(!allowed(r#"git diff "${X}""#));
+        assert!(!allowed("git diff ~/x"));
+        assert!(!allowed("git diff src/*.rs"));
+    }
+}
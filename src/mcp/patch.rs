@@ -1,6 +1,7 @@
 use std::{borrow::Cow, str::FromStr};
 
 use anyhow::{Context as _, Result};
+use serde::Serialize;
 
 /// Represents the range of lines in a hunk header
 #[derive(Clone, Debug)]
@@ -38,7 +39,7 @@ impl HunkLine {
         }
     }
 
-    pub fn as_patch_line(&self) -> Cow<str> {
+    pub fn as_patch_line(&self) -> Cow<'_, str> {
         match self {
             HunkLine::Context(s) => Cow::Owned(format!(" {s}")),
             HunkLine::Added(s) => Cow::Owned(format!("+{s}")),
@@ -47,6 +48,88 @@ impl HunkLine {
     }
 }
 
+/// How strictly hunk lines are compared with file lines, from strictest to loosest
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MatchTier {
+    #[default]
+    Exact,
+    /// Trailing whitespace is ignored
+    TrailingWhitespace,
+    /// Leading whitespace may differ by the same amount on every line; added lines are
+    /// re-indented to match the file
+    Indentation,
+    /// Lines only need to be similar by edit distance; tried last, see `find_similar`
+    Similar,
+}
+
+impl MatchTier {
+    /// Tiers tried in order for hunks that did not match at a stricter one
+    const FALLBACKS: [MatchTier; 2] = [MatchTier::TrailingWhitespace, MatchTier::Indentation];
+
+    fn lines_match(self, expected: &str, actual: &str) -> bool {
+        match self {
+            MatchTier::Exact => expected == actual,
+            MatchTier::TrailingWhitespace => expected.trim_end() == actual.trim_end(),
+            MatchTier::Indentation => expected.trim() == actual.trim(),
+            MatchTier::Similar => line_similarity(expected, actual) >= SIMILARITY_THRESHOLD,
+        }
+    }
+
+    /// Note appended to a hunk's report line; empty for exact matches
+    fn note(self) -> &'static str {
+        match self {
+            MatchTier::Exact => "",
+            MatchTier::TrailingWhitespace => " (matched ignoring trailing whitespace)",
+            MatchTier::Indentation => {
+                " (matched ignoring indentation; added lines were re-indented)"
+            }
+            MatchTier::Similar => {
+                " (fuzzy match: some context or removed lines differ slightly from the file)"
+            }
+        }
+    }
+}
+
+/// Lowest similarity, from 0 to 1, of a hunk's context and removed lines to a region of the file
+/// for the region to be a fuzzy match
+const SIMILARITY_THRESHOLD: f64 = 0.8;
+
+/// Regions scoring within this much of the best one are equally good fuzzy matches
+const SIMILARITY_MARGIN: f64 = 0.05;
+
+/// How close to the line its header claims a region must start for the claim to choose it among
+/// equally good fuzzy matches
+const NEAR_CLAIM_LINES: usize = 10;
+
+/// Similarity of two lines from 0 to 1 by edit distance, ignoring surrounding whitespace
+fn line_similarity(expected: &str, actual: &str) -> f64 {
+    strsim::normalized_levenshtein(expected.trim(), actual.trim())
+}
+
+/// Width of a line's indentation, counting a tab as four columns
+fn indent_width(line: &str) -> usize {
+    line.chars()
+        .take_while(|c| c.is_whitespace())
+        .map(|c| if c == '\t' { 4 } else { 1 })
+        .sum()
+}
+
+/// Shifts a line's indentation by `delta` columns, indenting with tabs or spaces
+fn reindent(line: &str, delta: isize, use_tabs: bool) -> String {
+    let rest = line.trim_start();
+    if rest.is_empty() {
+        return line.to_string();
+    }
+    let width = indent_width(line).saturating_add_signed(delta);
+    let indent = if use_tabs {
+        format!("{}{}", "\t".repeat(width / 4), " ".repeat(width % 4))
+    } else {
+        " ".repeat(width)
+    };
+    format!("{indent}{rest}")
+}
+
 /// Represents a hunk in a patch
 #[derive(Clone, Debug)]
 pub struct Hunk {
@@ -58,6 +141,12 @@ pub struct Hunk {
 
     /// The original full hunk body
     pub body: String,
+
+    /// Position of the hunk in the patch, starting at 0
+    pub index: usize,
+
+    /// How the hunk matched the file, once it is found
+    pub tier: MatchTier,
 }
 
 impl<'a> From<&'a Hunk> for Cow<'a, Hunk> {
@@ -94,15 +183,14 @@ impl Hunk {
             .map_or_else(|| self.lines.len(), |(i, _)| i)
     }
 
-    pub fn matches(&self, line: &str, index: usize, log: bool) -> bool {
+    pub fn matches(&self, line: &str, index: usize, tier: MatchTier, log: bool) -> bool {
         let expected = self
             .matchable_lines()
             .skip(index)
             .map(HunkLine::content)
             .next();
 
-        // let outcome = expected.map(str::trim) == Some(line.trim());
-        let outcome = expected == Some(line);
+        let outcome = expected.is_some_and(|expected| tier.lines_match(expected, line));
 
         if log {
             if outcome {
@@ -165,17 +253,89 @@ pub struct Candidate<'a> {
     current_line: usize,
 
     hunk: Cow<'a, Hunk>,
+
+    tier: MatchTier,
+
+    /// Columns the file's indentation differs from the hunk's, for `MatchTier::Indentation`
+    indent_delta: Option<isize>,
 }
 
 impl<'a> Candidate<'a> {
-    pub fn new(line: usize, hunk: impl Into<Cow<'a, Hunk>>) -> Self {
+    pub fn new(line: usize, hunk: impl Into<Cow<'a, Hunk>>, tier: MatchTier) -> Self {
         Self {
             start: line,
             current_line: 0,
             hunk: hunk.into(),
+            tier,
+            indent_delta: None,
         }
     }
 
+    /// A candidate at the same position with a changed hunk
+    fn with_hunk(&self, hunk: Hunk, current_line: usize) -> Candidate<'a> {
+        Candidate {
+            start: self.start,
+            current_line,
+            hunk: Cow::Owned(hunk),
+            tier: self.tier,
+            indent_delta: self.indent_delta,
+        }
+    }
+
+    /// Replaces the hunk's context and removed lines with the file lines they matched, and
+    /// re-indents the added lines, so the hunk applies exactly
+    ///
+    /// Each added line is shifted by as much as the nearest matched line before it, or after it
+    /// at the start of the hunk, so hunks indented with a different width than the file still
+    /// line up with their neighbours.
+    fn conform(&mut self, file_lines: &[&str]) {
+        let matched =
+            &file_lines[self.start..(self.start + self.current_line).min(file_lines.len())];
+        let use_tabs = matched.iter().any(|line| line.starts_with('\t'));
+        let tier = self.tier;
+
+        let hunk = self.hunk.to_mut();
+        // How far the file's indentation differs from the hunk's at each non-blank matched line
+        let mut deltas: Vec<Option<isize>> = Vec::with_capacity(hunk.lines.len());
+        let mut file_line = matched.iter();
+        for line in &mut hunk.lines {
+            match line {
+                HunkLine::Context(content) | HunkLine::Removed(content) => {
+                    let actual = file_line.next();
+                    #[allow(clippy::cast_possible_wrap)]
+                    deltas.push(
+                        actual
+                            .filter(|actual| {
+                                !actual.trim().is_empty() && !content.trim().is_empty()
+                            })
+                            .map(|actual| {
+                                indent_width(actual) as isize - indent_width(content) as isize
+                            }),
+                    );
+                    if let Some(actual) = actual {
+                        *content = (*actual).to_string();
+                    }
+                }
+                HunkLine::Added(_) => deltas.push(None),
+            }
+        }
+
+        if matches!(tier, MatchTier::Indentation | MatchTier::Similar) {
+            for (i, line) in hunk.lines.iter_mut().enumerate() {
+                if let HunkLine::Added(content) = line {
+                    let delta = deltas[..i]
+                        .iter()
+                        .rev()
+                        .chain(&deltas[i + 1..])
+                        .find_map(|delta| *delta)
+                        .unwrap_or(0);
+                    *content = reindent(content, delta, use_tabs);
+                }
+            }
+        }
+        hunk.tier = tier;
+    }
+
     /// Number difference in visible lines between the source and destination for the next hunk
     ///
     /// If lines were added, the following hunk will start at an increased line number, if lines
@@ -186,8 +346,34 @@ impl<'a> Candidate<'a> {
             - self.hunk.lines.iter().filter(|l| l.is_removed()).count() as isize
     }
 
-    pub fn next_line_matches(&self, line: &str) -> bool {
-        self.hunk.matches(line, self.current_line, true)
+    /// Whether the file line matches the next hunk line; at `MatchTier::Indentation`, every
+    /// non-blank line must be shifted by the same amount as the first one
+    pub fn next_line_matches(&mut self, line: &str) -> bool {
+        if !self.hunk.matches(line, self.current_line, self.tier, true) {
+            return false;
+        }
+        if self.tier != MatchTier::Indentation || line.trim().is_empty() {
+            return true;
+        }
+        let expected = self
+            .hunk
+            .matchable_lines()
+            .nth(self.current_line)
+            .map_or(0, |l| indent_width(l.content()));
+        #[allow(clippy::cast_possible_wrap)]
+        let delta = indent_width(line) as isize - expected as isize;
+        *self.indent_delta.get_or_insert(delta) == delta
+    }
+
+    /// The line after the last file line the candidate matched
+    fn end(&self) -> usize {
+        self.start + self.current_line
+    }
+
+    /// Lines between the candidate and the line its hunk's header claims
+    fn claim_distance(&self) -> usize {
+        self.start
+            .abs_diff(self.hunk.header.source.start.saturating_sub(1))
     }
 
     pub fn is_complete(&self) -> bool {
@@ -244,6 +430,8 @@ impl FromStr for Hunk {
             header,
             lines,
             body: s.into(),
+            index: 0,
+            tier: MatchTier::Exact,
         })
     }
 }
@@ -276,8 +464,14 @@ impl FromStr for HunkHeader {
             anyhow::bail!("Invalid hunk header format");
         }
 
-        let old_range = parts[1].split(',').collect::<Vec<&str>>();
-        let new_range = parts[2].split(',').collect::<Vec<&str>>();
+        // git leaves out the line count when it is 1, as in `@@ -1 +0,0 @@`
+        let mut old_range = parts[1].split(',').collect::<Vec<&str>>();
+        let mut new_range = parts[2].split(',').collect::<Vec<&str>>();
+        for range in [&mut old_range, &mut new_range] {
+            if range.len() == 1 {
+                range.push("1");
+            }
+        }
 
         if old_range.len() != 2 || new_range.len() != 2 {
             anyhow::bail!("Invalid range format in hunk header");
@@ -316,7 +510,8 @@ pub fn parse_hunks(patch: &str) -> Result<Vec<Hunk>> {
     for line in patch.lines() {
         if line.starts_with("@@") {
             if !current_hunk_lines.is_empty() {
-                let hunk = Hunk::from_str(&current_hunk_lines.join("\n"))?;
+                let mut hunk = Hunk::from_str(&current_hunk_lines.join("\n"))?;
+                hunk.index = hunks.len();
                 hunks.push(hunk);
             }
 
@@ -327,7 +522,8 @@ pub fn parse_hunks(patch: &str) -> Result<Vec<Hunk>> {
     }
 
     if !current_hunk_lines.is_empty() {
-        let hunk = Hunk::from_str(&current_hunk_lines.join("\n"))?;
+        let mut hunk = Hunk::from_str(&current_hunk_lines.join("\n"))?;
+        hunk.index = hunks.len();
         hunks.push(hunk);
     }
 
@@ -340,14 +536,140 @@ pub fn parse_hunks(patch: &str) -> Result<Vec<Hunk>> {
 ///
 /// However, with a patch we can reasonably fix the headers
 /// by searching in the neighboring lines of the original hunk header
+///
+/// Hunks are matched exactly first. Those that don't match are retried ignoring trailing
+/// whitespace, then ignoring indentation, and are rewritten to the file's actual lines.
+///
+/// Hunks that still don't match are accepted where their lines are similar enough to the file's,
+/// see `find_similar`.
 pub fn find_candidates<'a>(content: &str, hunks: &'a [Hunk]) -> Vec<Candidate<'a>> {
+    let mut candidates =
+        find_candidates_at(content, &hunks.iter().collect::<Vec<_>>(), MatchTier::Exact);
+
+    for tier in MatchTier::FALLBACKS {
+        let unmatched = unmatched_hunks(hunks, &candidates);
+        if unmatched.is_empty() {
+            break;
+        }
+        candidates.extend(find_candidates_at(content, &unmatched, tier));
+    }
+
+    let lines: Vec<&str> = content.lines().collect();
+    for hunk in unmatched_hunks(hunks, &candidates) {
+        let Ok(Some(start)) = find_similar(&lines, hunk) else {
+            continue;
+        };
+        let len = hunk.matchable_lines().count();
+        // Another hunk already changes these lines
+        if candidates
+            .iter()
+            .any(|c| c.is_complete() && c.start < start + len && start < c.start + c.current_line)
+        {
+            continue;
+        }
+        let mut candidate = Candidate::new(start, hunk, MatchTier::Similar);
+        candidate.current_line = len;
+        candidate.conform(&lines);
+        candidates.push(candidate);
+    }
+
+    // Rebuilding the hunks expects the candidates in file order
+    candidates.sort_by_key(|c| c.start);
+    candidates
+}
+
+/// Hunks without a complete candidate
+fn unmatched_hunks<'a>(hunks: &'a [Hunk], candidates: &[Candidate]) -> Vec<&'a Hunk> {
+    hunks
+        .iter()
+        .filter(|h| {
+            !candidates
+                .iter()
+                .any(|c| c.is_complete() && c.hunk.index == h.index)
+        })
+        .collect()
+}
+
+/// Finds where a hunk's context and removed lines are similar to the file's by edit distance
+///
+/// Regions scoring within `SIMILARITY_MARGIN` of the best one are equally good; the one nearest
+/// the line the hunk claims is chosen if it is within `NEAR_CLAIM_LINES` of it. Otherwise the
+/// match is ambiguous and the starts of the regions are returned as the error.
+fn find_similar(lines: &[&str], hunk: &Hunk) -> Result<Option<usize>, Vec<usize>> {
+    let expected: Vec<&HunkLine> = hunk.matchable_lines().collect();
+    if expected.is_empty() || expected.len() > lines.len() {
+        return Ok(None);
+    }
+
+    let mut regions: Vec<(usize, f64)> = Vec::new();
+    'regions: for start in 0..=lines.len() - expected.len() {
+        let mut total = 0.0;
+        for (line, actual) in expected.iter().zip(&lines[start..]) {
+            let similarity = line_similarity(line.content(), actual);
+            // A removed line that differs too much could delete the wrong code
+            if line.is_removed() && similarity < SIMILARITY_THRESHOLD {
+                continue 'regions;
+            }
+            total += similarity;
+        }
+        #[allow(clippy::cast_precision_loss)]
+        let score = total / expected.len() as f64;
+        if score >= SIMILARITY_THRESHOLD {
+            regions.push((start, score));
+        }
+    }
+
+    // Overlapping regions are the same match shifted by a few lines; keep the best of them
+    regions.sort_by(|a, b| b.1.total_cmp(&a.1));
+    let mut distinct: Vec<(usize, f64)> = Vec::new();
+    for (start, score) in regions {
+        if distinct
+            .iter()
+            .all(|(other, _)| start.abs_diff(*other) >= expected.len())
+        {
+            distinct.push((start, score));
+        }
+    }
+
+    let Some(&(_, best)) = distinct.first() else {
+        return Ok(None);
+    };
+    let claimed = hunk.header.source.start.saturating_sub(1);
+    let mut contenders: Vec<usize> = distinct
+        .iter()
+        .filter(|(_, score)| best - score <= SIMILARITY_MARGIN)
+        .map(|(start, _)| *start)
+        .collect();
+    contenders.sort_by_key(|start| start.abs_diff(claimed));
+    match contenders[..] {
+        [only] => Ok(Some(only)),
+        [nearest, next, ..]
+            if nearest.abs_diff(claimed) <= NEAR_CLAIM_LINES
+                && nearest.abs_diff(claimed) < next.abs_diff(claimed) =>
+        {
+            Ok(Some(nearest))
+        }
+        _ => {
+            contenders.sort_unstable();
+            Err(contenders)
+        }
+    }
+}
+
+/// Finds the candidates of the hunks, comparing lines at the given tier
+fn find_candidates_at<'a>(
+    content: &str,
+    hunks: &[&'a Hunk],
+    tier: MatchTier,
+) -> Vec<Candidate<'a>> {
     let mut candidates = Vec::new();
 
     for (line_n, line) in content.lines().enumerate() {
-        // 1. Check if a hunk matches the line, then create a candidate if it does
-        if let Some(hunk) = hunks.iter().find(|h| h.matches(line, 0, false)) {
+        // 1. Create a candidate for every hunk whose first line matches the line; hunks often
+        // start with the same context line, e.g. a closing brace
+        for hunk in hunks.iter().filter(|h| h.matches(line, 0, tier, false)) {
             tracing::trace!(line, "Found hunk match; creating new candidate");
-            candidates.push(Candidate::new(line_n, hunk));
+            candidates.push(Candidate::new(line_n, *hunk, tier));
         }
 
         // 2. For each active candidate, check if the next line matches. If it does, increment the
@@ -366,20 +688,17 @@ pub fn find_candidates<'a>(content: &str, hunks: &'a [Hunk]) -> Vec<Candidate<'a
                 // candidate. This helps with LLMs misjudging whitespace in the context
                 let mut new_hunk: Hunk = c.hunk.clone().into_owned();
                 new_hunk.insert_line_at(HunkLine::Context(line.into()), c.current_line);
-                let mut new_candidate = Candidate::new(c.start, new_hunk);
-                new_candidate.current_line = c.current_line + 1;
-
-                new_candidates.push(new_candidate);
+                new_candidates.push(c.with_hunk(new_hunk, c.current_line + 1));
                 false
             } else if c
                 .hunk
                 .lines.iter()
-                .skip(c.hunk.real_index(c.current_line + 1))
+                .skip(c.hunk.real_index(c.current_line))
                 .all(HunkLine::is_context)
             {
-                // If the following remaining lines, including this one, are context only, accept
-                // the current AI overlords incompetence and add a finished candidate without the
-                // remaining lines.
+                // If the mismatched line and all lines after it are context, accept the current AI
+                // overlords incompetence and add a finished candidate without the remaining
+                // lines. Removed or added lines are never dropped.
                 tracing::trace!(line, "Mismatch; remaining is context only, adding finished candidate without the remaining lines");
                 let real_index = c.hunk.real_index(c.current_line);
                 let mut new_hunk = c.hunk.clone().into_owned();
@@ -390,9 +709,7 @@ pub fn find_candidates<'a>(content: &str, hunks: &'a [Hunk]) -> Vec<Candidate<'a
                     .cloned()
                     .collect();
 
-                let mut new_candidate = Candidate::new(c.start, new_hunk);
-                new_candidate.current_line = c.current_line;
-                new_candidates.push(new_candidate);
+                new_candidates.push(c.with_hunk(new_hunk, c.current_line));
                 false
             } else {
                 tracing::trace!(line, "Removing candidate");
@@ -402,6 +719,13 @@ pub fn find_candidates<'a>(content: &str, hunks: &'a [Hunk]) -> Vec<Candidate<'a
         candidates.append(&mut new_candidates);
     }
 
+    if tier != MatchTier::Exact {
+        let file_lines: Vec<&str> = content.lines().collect();
+        for candidate in candidates.iter_mut().filter(|c| c.is_complete()) {
+            candidate.conform(&file_lines);
+        }
+    }
+
     candidates
 }
 
@@ -409,13 +733,37 @@ pub fn find_candidates<'a>(content: &str, hunks: &'a [Hunk]) -> Vec<Candidate<'a
 ///
 /// Filters out duplicates. The resulting hunks should result in a valid patch.
 pub fn rebuild_hunks(candidates: &[Candidate<'_>]) -> Vec<Hunk> {
-    // Assume that the candidates are sorted by the start line
-    // Then we can just iterate over the candidates and update the ranges
+    // A hunk can match in several places. Prefer the candidate closest to the line its header
+    // claims
+    let mut chosen: Vec<&Candidate<'_>> = Vec::new();
+    for candidate in candidates.iter().filter(|c| c.is_complete()) {
+        match chosen
+            .iter_mut()
+            .find(|c| c.hunk.index == candidate.hunk.index)
+        {
+            Some(existing) if existing.claim_distance() <= candidate.claim_distance() => {}
+            Some(existing) => *existing = candidate,
+            None => chosen.push(candidate),
+        }
+    }
 
+    // Then we can just iterate over the candidates in file order and update the ranges. Hunks
+    // changing lines an earlier hunk already changes can't both apply, so only the first is kept
+    chosen.sort_by_key(|c| c.start);
     let mut current_offset: isize = 0;
     let mut hunks: Vec<Hunk> = Vec::new();
+    let mut end = 0;
+
+    for candidate in chosen {
+        if candidate.start < end {
+            tracing::warn!(
+                hunk = candidate.hunk.index + 1,
+                "Dropping hunk that overlaps another hunk"
+            );
+            continue;
+        }
+        end = candidate.end();
 
-    for candidate in candidates {
         let source_header = candidate.updated_source_header();
 
         let dest_header = candidate.updated_dest_header(current_offset);
@@ -425,50 +773,608 @@ pub fn rebuild_hunks(candidates: &[Candidate<'_>]) -> Vec<Hunk> {
         let mut hunk = candidate.hunk.clone().into_owned();
         hunk.header.fixed_source = Some(source_header);
         hunk.header.fixed_dest = Some(dest_header);
+        hunks.push(hunk);
+    }
 
-        // Filter duplicates. A hunk is a duplicate if the hunk body is the same. If a duplicate
-        // is detected, prefer the one with the fixed_source closest to the original source line
-        // If so, we swap it with the existing hunk.
+    hunks
+}
+
+/// Takes the file lines from the original patch if possible, then rebuilds the patch
+pub fn rebuild_patch(original: &str, hunks: &[Hunk]) -> Result<String> {
+    let mut new_patch = String::new();
+    for line in original
+        .lines()
+        .take_while(|l| !l.starts_with("@@"))
+        .filter(|l| l.starts_with("--- ") || l.starts_with("+++ "))
+    {
+        new_patch.push_str(line);
+        new_patch.push('\n');
+    }
 
-        if let Some(existing) = hunks.iter_mut().find(|h| *h.body == hunk.body) {
-            let (Some(existing_source), Some(new_source)) =
-                (&existing.header.fixed_source, &hunk.header.fixed_source)
-            else {
-                tracing::warn!("Potential bad duplicate when rebuilding patch; could be a bug, please check the edit");
-                continue;
+    for hunk in hunks {
+        new_patch.push_str(&hunk.render_updated()?);
+    }
+
+    Ok(new_patch)
+}
+
+/// The part of a patch that changes one file
+#[derive(Clone, Debug)]
+pub struct FilePatch {
+    /// Path in the `---` header, without git's `a/` prefix; `None` for `/dev/null`
+    pub old_path: Option<String>,
+    /// Path in the `+++` header, without git's `b/` prefix; `None` for `/dev/null`
+    pub new_path: Option<String>,
+    /// The file is created, from `--- /dev/null` or `new file mode`
+    pub creates: bool,
+    /// The file is deleted, from `+++ /dev/null` or `deleted file mode`
+    pub deletes: bool,
+    /// The file's section of the patch, from its headers to the end of its last hunk
+    pub text: String,
+}
+
+impl FilePatch {
+    /// The file the patch changes, `None` if the section has no file headers
+    pub fn path(&self) -> Option<&str> {
+        self.new_path.as_deref().or(self.old_path.as_deref())
+    }
+
+    /// Whether the section has hunks, rather than only creating, deleting or renaming the file
+    pub fn has_hunks(&self) -> bool {
+        self.text.lines().any(|line| line.starts_with("@@"))
+    }
+}
+
+/// Reads the paths from a `diff --git a/old b/new` line
+fn git_diff_paths(line: &str) -> Option<(String, String)> {
+    let paths = line.strip_prefix("diff --git ")?.strip_prefix("a/")?;
+    let (old, new) = paths.split_once(" b/")?;
+    Some((old.to_string(), new.to_string()))
+}
+
+/// Reads the path from a `--- ` or `+++ ` header line, dropping a timestamp and git's prefix
+fn header_path(line: &str, git_prefix: &str) -> String {
+    let path = line[4..].split('\t').next().unwrap_or_default().trim_end();
+    let path = path
+        .strip_prefix('"')
+        .and_then(|p| p.strip_suffix('"'))
+        .unwrap_or(path);
+    path.strip_prefix(git_prefix).unwrap_or(path).to_string()
+}
+
+/// Splits a patch into the sections for each file
+///
+/// A section starts at a `diff --git` line, or at a `---` line followed by a `+++` line once
+/// the previous section has hunks. Text before the first section, such as a commit message, is
+/// dropped, as are sections without hunks unless they create, delete or rename a file. A patch
+/// that is only hunks, without file headers, gives a single section without paths.
+pub fn split_patch(patch: &str) -> Vec<FilePatch> {
+    let lines: Vec<&str> = patch.lines().collect();
+    let mut sections: Vec<Vec<&str>> = Vec::new();
+    let mut current: Vec<&str> = Vec::new();
+    let mut has_hunks = false;
+
+    for (i, line) in lines.iter().enumerate() {
+        let file_header = line.starts_with("--- ")
+            && lines
+                .get(i + 1)
+                .is_some_and(|next| next.starts_with("+++ "));
+        if line.starts_with("diff --git ") || (file_header && has_hunks) {
+            sections.push(std::mem::take(&mut current));
+            has_hunks = false;
+        }
+        has_hunks |= line.starts_with("@@");
+        current.push(line);
+    }
+    sections.push(current);
+
+    sections
+        .into_iter()
+        .map(|section| {
+            let header = section.iter().take_while(|line| !line.starts_with("@@"));
+            let mut file_patch = FilePatch {
+                old_path: None,
+                new_path: None,
+                creates: false,
+                deletes: false,
+                text: String::new(),
             };
+            for line in header {
+                if let Some((old, new)) = git_diff_paths(line) {
+                    file_patch.old_path = Some(old);
+                    file_patch.new_path = Some(new);
+                } else if line.starts_with("new file mode") {
+                    file_patch.creates = true;
+                } else if line.starts_with("deleted file mode") {
+                    file_patch.deletes = true;
+                } else if let Some(path) = line.strip_prefix("rename from ") {
+                    file_patch.old_path = Some(path.to_string());
+                } else if let Some(path) = line.strip_prefix("rename to ") {
+                    file_patch.new_path = Some(path.to_string());
+                } else if line.starts_with("--- ") {
+                    file_patch.old_path = Some(header_path(line, "a/"));
+                } else if line.starts_with("+++ ") {
+                    file_patch.new_path = Some(header_path(line, "b/"));
+                }
+            }
+            if file_patch.old_path.as_deref() == Some("/dev/null") {
+                file_patch.old_path = None;
+                file_patch.creates = true;
+            }
+            if file_patch.new_path.as_deref() == Some("/dev/null") {
+                file_patch.new_path = None;
+                file_patch.deletes = true;
+            }
+            if file_patch.creates {
+                file_patch.old_path = None;
+            }
+            if file_patch.deletes {
+                file_patch.new_path = None;
+            }
+            file_patch.text = section.join("\n");
+            file_patch.text.push('\n');
+            file_patch
+        })
+        .filter(|file_patch| {
+            file_patch.has_hunks()
+                || file_patch.creates
+                || file_patch.deletes
+                || file_patch.old_path.is_some() && file_patch.old_path != file_patch.new_path
+        })
+        .collect()
+}
 
-            #[allow(clippy::cast_possible_wrap)]
-            if ((existing_source.start as isize)
-                .saturating_sub_unsigned(existing.header.source.start))
-            .abs()
-                < ((new_source.start as isize).saturating_sub_unsigned(hunk.header.source.start))
-                    .abs()
-            {
-                continue;
+/// The file region most similar to a hunk that could not be matched
+#[derive(Clone, Debug, Serialize)]
+pub struct Mismatch {
+    /// First and last line of the region, 1-based
+    pub start_line: usize,
+    pub end_line: usize,
+    /// Context and removed lines of the hunk that equal the line at the same position
+    pub matching_lines: usize,
+    pub hunk_lines: usize,
+    /// First line of the region that differs from the hunk
+    pub differing_line: usize,
+    pub expected: String,
+    /// `None` when the file ends before the hunk does
+    pub actual: Option<String>,
+}
+
+/// What happened to a hunk when the patch was applied
+#[derive(Clone, Debug, Serialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum HunkOutcome {
+    /// Matched at the line its header claimed
+    Applied { line: usize, tier: MatchTier },
+    /// Matched `offset` lines away from the line its header claimed
+    FuzzyApplied {
+        line: usize,
+        offset: isize,
+        tier: MatchTier,
+    },
+    /// Matched at `line`, but another hunk of the patch changes some of the same lines, so it
+    /// was not applied
+    Overlapping { line: usize, other_hunk: usize },
+    /// Its context and removed lines are not in the file
+    Failed {
+        closest: Option<Mismatch>,
+        /// 1-based start lines of the regions it is equally similar to, when the fuzzy match
+        /// was refused as ambiguous
+        #[serde(skip_serializing_if = "Vec::is_empty")]
+        ambiguous: Vec<usize>,
+    },
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct HunkReport {
+    /// 1-based position of the hunk in the patch
+    pub hunk: usize,
+    /// Start line from the hunk header
+    pub claimed_line: usize,
+    #[serde(flatten)]
+    pub outcome: HunkOutcome,
+}
+
+impl HunkReport {
+    pub fn failed(&self) -> bool {
+        matches!(
+            self.outcome,
+            HunkOutcome::Failed { .. } | HunkOutcome::Overlapping { .. }
+        )
+    }
+}
+
+/// Finds the region of the file sharing the most lines with the hunk, preferring the one
+/// nearest to the claimed line
+///
+/// `None` when no line of the hunk is in the file. Hunks whose lines are all in the file matched,
+/// and are reported as applied or overlapping instead.
+fn closest_region(lines: &[&str], hunk: &Hunk) -> Option<Mismatch> {
+    let expected: Vec<&str> = hunk.matchable_lines().map(HunkLine::content).collect();
+    let claimed = hunk.header.source.start.saturating_sub(1);
+
+    let mut best: Option<(usize, usize)> = None;
+    for start in 0..lines.len() {
+        let score = expected
+            .iter()
+            .zip(&lines[start..])
+            .filter(|(e, a)| e == a)
+            .count();
+        let better = match best {
+            None => score > 0,
+            Some((best_start, best_score)) => {
+                score > best_score
+                    || (score == best_score
+                        && start.abs_diff(claimed) < best_start.abs_diff(claimed))
             }
-            *existing = hunk;
-        } else {
-            hunks.push(hunk);
+        };
+        if better {
+            best = Some((start, score));
         }
     }
 
+    let (start, score) = best?;
+    let differing = (0..expected.len()).find(|&j| lines.get(start + j) != Some(&expected[j]))?;
+    Some(Mismatch {
+        start_line: start + 1,
+        end_line: (start + expected.len()).min(lines.len()),
+        matching_lines: score,
+        hunk_lines: expected.len(),
+        differing_line: start + differing + 1,
+        expected: expected[differing].to_string(),
+        actual: lines.get(start + differing).map(|line| line.to_string()),
+    })
+}
+
+/// Reports for each hunk whether and where it matched
+fn hunk_reports(
+    content: &str,
+    hunks: &[Hunk],
+    candidates: &[Candidate<'_>],
+    repaired: &[Hunk],
+) -> Vec<HunkReport> {
+    let lines: Vec<&str> = content.lines().collect();
     hunks
+        .iter()
+        .enumerate()
+        .map(|(i, hunk)| {
+            let claimed_line = hunk.header.source.start;
+            let found = repaired
+                .iter()
+                .find(|h| h.index == hunk.index)
+                .and_then(|h| Some((h.header.fixed_source.as_ref()?, h.tier)));
+            // A hunk that matched but was not repaired overlaps a hunk that was
+            let overlapping = candidates
+                .iter()
+                .filter(|c| c.is_complete() && c.hunk.index == hunk.index)
+                .min_by_key(|c| c.claim_distance())
+                .and_then(|candidate| {
+                    let other = repaired.iter().find(|h| {
+                        h.header.fixed_source.as_ref().is_some_and(|source| {
+                            source.start < candidate.end()
+                                && candidate.start < source.start + source.range
+                        })
+                    })?;
+                    Some((candidate.start + 1, other.index + 1))
+                });
+            let outcome = match (found, overlapping) {
+                (Some((source, tier)), _) if source.start + 1 == claimed_line => {
+                    HunkOutcome::Applied {
+                        line: claimed_line,
+                        tier,
+                    }
+                }
+                #[allow(clippy::cast_possible_wrap)]
+                (Some((source, tier)), _) => HunkOutcome::FuzzyApplied {
+                    line: source.start + 1,
+                    offset: (source.start + 1) as isize - claimed_line as isize,
+                    tier,
+                },
+                (None, Some((line, other_hunk))) => HunkOutcome::Overlapping { line, other_hunk },
+                (None, None) => HunkOutcome::Failed {
+                    closest: closest_region(&lines, hunk),
+                    ambiguous: find_similar(&lines, hunk)
+                        .err()
+                        .unwrap_or_default()
+                        .into_iter()
+                        .map(|start| start + 1)
+                        .collect(),
+                },
+            };
+            HunkReport {
+                hunk: i + 1,
+                claimed_line,
+                outcome,
+            }
+        })
+        .collect()
 }
 
-/// Takes the file lines from the original patch if possible, then rebuilds the patch
-pub fn rebuild_patch(original: &str, hunks: &[Hunk]) -> Result<String> {
-    let mut new_patch = original.lines().take(2).collect::<Vec<_>>().join("\n");
-    new_patch.push('\n');
+/// Renders the per-hunk report of a file
+pub fn render_hunk_reports(reports: &[HunkReport]) -> String {
+    let mut out = String::new();
+    for report in reports {
+        match &report.outcome {
+            HunkOutcome::Applied { line, tier } => out.push_str(&format!(
+                "  Hunk {}: applied at line {}{}\n",
+                report.hunk,
+                line,
+                tier.note()
+            )),
+            HunkOutcome::FuzzyApplied { line, offset, tier } => out.push_str(&format!(
+                "  Hunk {}: applied at line {}, {} lines {} the claimed line {}{}\n",
+                report.hunk,
+                line,
+                offset.unsigned_abs(),
+                if *offset > 0 { "after" } else { "before" },
+                report.claimed_line,
+                tier.note()
+            )),
+            HunkOutcome::Overlapping { line, other_hunk } => out.push_str(&format!(
+                "  Hunk {}: failed; it matches at line {}, but hunk {} changes some of the same lines. Merge the two hunks into one\n",
+                report.hunk, line, other_hunk
+            )),
+            HunkOutcome::Failed { ambiguous, .. } if !ambiguous.is_empty() => {
+                let starts: Vec<String> = ambiguous.iter().map(|line| line.to_string()).collect();
+                out.push_str(&format!(
+                    "  Hunk {}: failed; it is about equally similar to the regions at lines {}, so none was chosen. Add context lines that tell them apart or fix the line numbers in its header\n",
+                    report.hunk,
+                    starts.join(", ")
+                ));
+            }
+            HunkOutcome::Failed { closest: None, .. } => out.push_str(&format!(
+                "  Hunk {}: failed; none of its context or removed lines are in the file\n",
+                report.hunk
+            )),
+            HunkOutcome::Failed {
+                closest: Some(closest),
+                ..
+            } => {
+                out.push_str(&format!(
+                    "  Hunk {}: failed; closest region is lines {}-{} ({} of {} lines match)\n",
+                    report.hunk,
+                    closest.start_line,
+                    closest.end_line,
+                    closest.matching_lines,
+                    closest.hunk_lines
+                ));
+                out.push_str(&format!(
+                    "    first difference at line {}:\n      expected: {}\n      actual:   {}\n",
+                    closest.differing_line,
+                    closest.expected,
+                    closest.actual.as_deref().unwrap_or("<end of file>")
+                ));
+            }
+        }
+    }
+    out
+}
 
-    debug_assert!(
-        !new_patch.is_empty(),
-        "Original file lines in patch tools are empty"
-    );
+/// A file's patch after its hunks were matched against the file and applied
+pub struct AppliedPatch {
+    /// Hunks as written in the patch
+    pub hunks: Vec<Hunk>,
+    /// Hunks whose location in the file was found, with corrected headers
+    pub repaired: Vec<Hunk>,
+    /// The patch rebuilt from the repaired hunks
+    pub patch: String,
+    /// The file content with the repaired hunks applied
+    pub content: String,
+    /// Whether and where each hunk matched
+    pub reports: Vec<HunkReport>,
+}
 
-    for hunk in hunks {
-        new_patch.push_str(&hunk.render_updated()?);
+impl AppliedPatch {
+    /// Number of hunks whose context could not be matched to the file
+    pub fn failed_count(&self) -> usize {
+        self.reports.iter().filter(|r| r.failed()).count()
     }
+}
 
-    Ok(new_patch)
-}
\ No newline at end of file
+/// Repairs the hunk headers of a file's patch against its content and applies it
+pub fn apply_patch(original: &str, patch: &str) -> Result<AppliedPatch> {
+    let hunks = parse_hunks(patch).context("Failed to parse patch")?;
+    // A patch that only renames or deletes the file leaves its content as it is
+    if hunks.is_empty() {
+        return Ok(AppliedPatch {
+            hunks,
+            repaired: Vec::new(),
+            patch: String::new(),
+            content: original.to_string(),
+            reports: Vec::new(),
+        });
+    }
+
+    // Patches are very strict on the last line being a newline
+    let mut original = original.to_string();
+    if !original.ends_with('\n') {
+        original.push('\n');
+    }
+
+    // Find candidates for each hunk in the file and rebuild them with corrected line numbers
+    let candidates = find_candidates(&original, &hunks);
+    let repaired = rebuild_hunks(&candidates);
+    let patch = rebuild_patch(patch, &repaired).context("Failed to render fixed patch")?;
+
+    let diffy_patch = diffy::Patch::from_str(&patch).context("Failed to parse patch")?;
+    let content = diffy::apply(&original, &diffy_patch).context("Failed to apply patch")?;
+
+    let reports = hunk_reports(&original, &hunks, &candidates, &repaired);
+    Ok(AppliedPatch {
+        hunks,
+        repaired,
+        patch,
+        content,
+        reports,
+    })
+}
+
+/// Builds a file the patch creates from the added lines of its hunks
+pub fn create_file(patch: &str) -> Result<AppliedPatch> {
+    let hunks = parse_hunks(patch).context("Failed to parse patch")?;
+
+    let mut content = String::new();
+    let mut reports = Vec::new();
+    for (i, hunk) in hunks.iter().enumerate() {
+        let line = content.lines().count() + 1;
+        for hunk_line in &hunk.lines {
+            match hunk_line {
+                HunkLine::Added(added) => {
+                    content.push_str(added);
+                    content.push('\n');
+                }
+                HunkLine::Context(marker) if marker.starts_with('\\') => {
+                    // `\ No newline at end of file`
+                    if content.ends_with('\n') {
+                        content.pop();
+                    }
+                }
+                HunkLine::Context(blank) if blank.is_empty() => {}
+                _ => anyhow::bail!(
+                    "Hunk {} has context or removed lines, but the file is new; its hunks can only add lines",
+                    i + 1
+                ),
+            }
+        }
+        reports.push(HunkReport {
+            hunk: i + 1,
+            claimed_line: line,
+            outcome: HunkOutcome::Applied {
+                line,
+                tier: MatchTier::Exact,
+            },
+        });
+    }
+
+    // The `---`/`+++` headers and the hunks, without git's extended header lines
+    let mut patch = rebuild_patch(patch, &[])?;
+    for hunk in &hunks {
+        patch.push_str(&hunk.body);
+        patch.push('\n');
+    }
+    Ok(AppliedPatch {
+        repaired: hunks.clone(),
+        hunks,
+        patch,
+        content,
+        reports,
+    })
+}
+
+/// Renders the change between two versions of a file as a unified diff
+pub fn render_diff(path: &str, original: &str, modified: &str) -> String {
+    diffy::DiffOptions::new()
+        .set_original_filename(format!("a/{path}"))
+        .set_modified_filename(format!("b/{path}"))
+        .create_patch(original, modified)
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FILE: &str =
+        "fn main() {\n    let x = 1;\n    let y = 3;\n    println!(\"{x} {y}\");\n}\n";
+
+    #[test]
+    fn fails_hunks_whose_removed_line_does_not_match() {
+        let patch = "--- a/src/main.rs\n+++ b/src/main.rs\n@@ -1,3 +1,3 @@\n fn main() {\n     let x = 1;\n-    let z = compute();\n+    let z = 5;\n";
+        let applied = apply_patch(FILE, patch).unwrap();
+        assert_eq!(applied.failed_count(), 1);
+        assert!(matches!(
+            applied.reports[0].outcome,
+            HunkOutcome::Failed { .. }
+        ));
+        assert_eq!(applied.content, FILE);
+    }
+
+    #[test]
+    fn fuzzy_matches_a_misremembered_removed_line() {
+        let patch = "--- a/src/main.rs\n+++ b/src/main.rs\n@@ -1,3 +1,3 @@\n fn main() {\n     let x = 1;\n-    let y = 33;\n+    let y = 5;\n";
+        let applied = apply_patch(FILE, patch).unwrap();
+        assert_eq!(applied.failed_count(), 0);
+        assert!(matches!(
+            applied.reports[0].outcome,
+            HunkOutcome::Applied {
+                tier: MatchTier::Similar,
+                ..
+            }
+        ));
+        assert_eq!(applied.content, FILE.replace("y = 3", "y = 5"));
+    }
+
+    #[test]
+    fn reindents_added_lines_like_their_neighbours() {
+        let file = "fn main() {\n    if true {\n        let y = 3;\n    }\n}\n";
+        let patch = "--- a/src/main.rs\n+++ b/src/main.rs\n@@ -1,4 +1,5 @@\n fn main() {\n   if true {\n-    let y = 3;\n+    let y = 4;\n+    let z = 5;\n   }\n";
+        let applied = apply_patch(file, patch).unwrap();
+        assert_eq!(applied.failed_count(), 0);
+        assert_eq!(
+            applied.content,
+            "fn main() {\n    if true {\n        let y = 4;\n        let z = 5;\n    }\n}\n"
+        );
+    }
+
+    #[test]
+    fn matches_hunks_that_start_with_the_same_line() {
+        let file = "fn a() {\n    1\n}\n\nfn b() {\n    2\n}\n\nfn c() {\n    3\n}\n";
+        let patch = "--- a/src/lib.rs\n+++ b/src/lib.rs\n@@ -3,3 +3,3 @@\n }\n \n-fn b() {\n+fn bb() {\n@@ -7,3 +7,3 @@\n }\n \n-fn c() {\n+fn cc() {\n";
+        let applied = apply_patch(file, patch).unwrap();
+        assert_eq!(applied.failed_count(), 0);
+        for report in &applied.reports {
+            assert!(matches!(
+                report.outcome,
+                HunkOutcome::Applied {
+                    tier: MatchTier::Exact,
+                    ..
+                }
+            ));
+        }
+        assert_eq!(
+            applied.content,
+            "fn a() {\n    1\n}\n\nfn bb() {\n    2\n}\n\nfn cc() {\n    3\n}\n"
+        );
+    }
+
+    #[test]
+    fn reports_hunks_that_overlap_another_hunk() {
+        let patch = "--- a/src/main.rs\n+++ b/src/main.rs\n@@ -1,2 +1,2 @@\n fn main() {\n-    let x = 1;\n+    let x = 2;\n@@ -2,2 +2,2 @@\n-    let x = 1;\n+    let x = 3;\n     let y = 3;\n";
+        let applied = apply_patch(FILE, patch).unwrap();
+        assert_eq!(applied.failed_count(), 1);
+        assert!(matches!(
+            applied.reports[1].outcome,
+            HunkOutcome::Overlapping {
+                line: 2,
+                other_hunk: 1
+            }
+        ));
+        assert!(
+            render_hunk_reports(&applied.reports).contains("hunk 1 changes some of the same lines")
+        );
+    }
+
+    #[test]
+    fn reports_repeated_hunks_separately() {
+        let hunk = "@@ -2,1 +2,1 @@\n-    let x = 1;\n+    let x = 2;\n";
+        let patch = format!("--- a/src/main.rs\n+++ b/src/main.rs\n{hunk}{hunk}");
+        let applied = apply_patch(FILE, &patch).unwrap();
+        assert!(matches!(
+            applied.reports[0].outcome,
+            HunkOutcome::Applied { line: 2, .. }
+        ));
+        assert!(matches!(
+            applied.reports[1].outcome,
+            HunkOutcome::Overlapping { other_hunk: 1, .. }
+        ));
+        assert_eq!(applied.content, FILE.replace("x = 1", "x = 2"));
+    }
+
+    #[test]
+    fn drops_trailing_context_that_does_not_match() {
+        let patch = "--- a/src/main.rs\n+++ b/src/main.rs\n@@ -1,4 +1,4 @@\n fn main() {\n-    let x = 1;\n+    let x = 2;\n     let y = 3;\n     println!(\"{y}\");\n";
+        let applied = apply_patch(FILE, patch).unwrap();
+        assert_eq!(applied.failed_count(), 0);
+        assert_eq!(applied.content, FILE.replace("x = 1", "x = 2"));
+    }
+}
@@ -0,0 +1,821 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+
+/// Programs that run another command given as their arguments
+const WRAPPERS: &[&str] = &["env", "sudo", "nohup", "exec", "command", "nice", "time", "timeout", "xargs", "stdbuf"];
+
+/// Options of wrappers that take the next word as their value, e.g. `sudo -u root`
+const WRAPPER_VALUE_OPTIONS: &[(&str, &[&str])] = &[
+    ("sudo", &["-u", "-g", "-h", "-p", "-C", "-D", "-r", "-t", "-U", "-T", "--user", "--group", "--chdir"]),
+    ("env", &["-u", "-C", "--unset", "--chdir"]),
+    ("exec", &["-a"]),
+    ("nice", &["-n", "--adjustment"]),
+    ("timeout", &["-s", "-k", "--signal", "--kill-after"]),
+    ("xargs", &["-a", "-d", "-E", "-I", "-L", "-n", "-P", "-s", "--arg-file", "--delimiter", "--max-args", "--max-procs"]),
+    ("stdbuf", &["-i", "-o", "-e"]),
+];
+
+/// Actions of `find` that run the command following them, up to `;` or `+`
+const FIND_EXEC_ACTIONS: &[&str] = &["-exec", "-execdir", "-ok", "-okdir"];
+
+/// Options before a program's subcommand that take the next word as their value, e.g. `git -C dir`
+const GLOBAL_VALUE_OPTIONS: &[(&str, &[&str])] = &[
+    ("git", &["-C", "-c", "--git-dir", "--work-tree", "--namespace", "--config-env", "--super-prefix"]),
+    ("cargo", &["-C", "-Z", "--config", "--color"]),
+];
+
+/// Flags with the spelling patterns use for them, e.g. `rm --force` is written `rm -f`
+const FLAG_ALIASES: &[(&str, &str, &str)] = &[
+    ("rm", "--recursive", "-r"),
+    ("rm", "-R", "-r"),
+    ("rm", "--force", "-f"),
+    ("rm", "--dir", "-d"),
+    ("cp", "--recursive", "-r"),
+    ("cp", "-R", "-r"),
+    ("cp", "--force", "-f"),
+    ("chmod", "--recursive", "-R"),
+    ("chown", "--recursive", "-R"),
+    ("git", "--force", "-f"),
+];
+
+/// Shell keywords that may precede the program of a simple command
+const LEADING_KEYWORDS: &[&str] = &["if", "then", "else", "elif", "do", "while", "until", "!", "{", "time"];
+
+/// Words that start or end a compound command without running anything themselves
+const STRUCTURAL_KEYWORDS: &[&str] = &["for", "select", "case", "esac", "done", "fi", "}"];
+
+/// Shell options that take a value, e.g. `bash -o pipefail -c "..."`
+const SHELL_VALUE_OPTIONS: &[&str] = &["-o", "+o", "-O", "+O", "--rcfile", "--init-file"];
+
+/// A word of a command line with its quotes removed
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Word {
+    pub text: String,
+    /// Whether the shell changes the word before running the command: `$` outside single
+    /// quotes, globs, brace expansion or `~`
+    pub expands: bool,
+}
+
+/// A single command of a command line, e.g. `cargo test -p foo` in `cd crate && cargo test -p foo`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SimpleCommand {
+    /// Program name without its directory, e.g. `rm` for `/bin/rm`
+    pub program: String,
+    /// The program as written, e.g. `/bin/rm` or `$EDITOR`
+    pub program_word: Word,
+    /// Variable assignments before the program, e.g. `RUST_LOG=debug` in `RUST_LOG=debug cargo run`
+    pub assignments: Vec<String>,
+    pub args: Vec<Word>,
+    /// Whether the command redirects input or output to files
+    pub redirects: bool,
+}
+
+impl fmt::Display for SimpleCommand {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.program)?;
+        for arg in &self.args {
+            write!(f, " {}", arg.text)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Default)]
+struct Tokenizer {
+    commands: Vec<SimpleCommand>,
+    words: Vec<Word>,
+    word: Option<String>,
+    /// The current word has characters the shell expands
+    word_expands: bool,
+    /// The current word has unquoted braces, which expand when they enclose a `,` or `..`
+    word_braces: bool,
+    redirects: bool,
+    /// The next word is the target of a redirection, not an argument
+    redirect_target: bool,
+    /// Delimiters of heredocs whose bodies start on the next line, and whether tabs are stripped
+    heredocs: Vec<(String, bool)>,
+    heredoc_pending: bool,
+}
+
+impl Tokenizer {
+    fn end_word(&mut self) {
+        let Some(word) = self.word.take() else {
+            return;
+        };
+        let expands = self.word_expands || (self.word_braces && (word.contains(',') || word.contains("..")));
+        self.word_expands = false;
+        self.word_braces = false;
+        if self.heredoc_pending {
+            // Quotes around the delimiter were already removed like for any other word
+            self.heredoc_pending = false;
+            if let Some((delimiter, _)) = self.heredocs.last_mut() {
+                *delimiter = word;
+            }
+        } else if self.redirect_target {
+            self.redirect_target = false;
+        } else {
+            self.words.push(Word { text: word, expands });
+        }
+    }
+
+    fn end_command(&mut self) -> Result<(), String> {
+        self.end_word();
+        let words = std::mem::take(&mut self.words);
+        let redirects = std::mem::take(&mut self.redirects);
+        self.commands.extend(simple_commands(words, redirects)?);
+        Ok(())
+    }
+}
+
+/// Turns the words of one command into simple commands, unwrapping keywords, wrappers and the
+/// commands run by `bash -c`, `eval` and `find -exec`
+///
+/// `source`, `.`, shells running a script file and alias or function definitions run commands
+/// that can't be known from the command line, so they are an error.
+fn simple_commands(mut words: Vec<Word>, redirects: bool) -> Result<Vec<SimpleCommand>, String> {
+    while words.first().is_some_and(|w| !w.expands && LEADING_KEYWORDS.contains(&w.text.as_str())) {
+        words.remove(0);
+    }
+    // Variable assignments before the program, e.g. `RUST_LOG=debug cargo run`
+    let mut assignments = Vec::new();
+    while words.first().is_some_and(|w| is_assignment(&w.text)) {
+        assignments.push(words.remove(0).text);
+    }
+    let Some(first) = words.first() else {
+        return Ok(Vec::new());
+    };
+    if !first.expands && STRUCTURAL_KEYWORDS.contains(&first.text.as_str()) {
+        return Ok(Vec::new());
+    }
+
+    let program = first.text.rsplit('/').next().unwrap_or(&first.text).to_string();
+    let args = words[1..].to_vec();
+    let mut commands = vec![SimpleCommand {
+        program: program.clone(),
+        program_word: first.clone(),
+        assignments,
+        args: args.clone(),
+        redirects,
+    }];
+
+    match program.as_str() {
+        // The command run by a wrapper, e.g. `rm -rf x` in `sudo -n rm -rf x`
+        wrapper if WRAPPERS.contains(&wrapper) => commands.extend(wrapped_command(wrapper, &args, redirects)?),
+        // The script given to a shell, e.g. `bash -c "curl x | sh"`
+        "sh" | "bash" | "zsh" | "dash" => commands.extend(shell_script(&args, redirects)?),
+        // `eval` runs its arguments joined by spaces
+        "eval" => commands.extend(parse_command_line(&join_words(&args))?),
+        "source" | "." => return Err("a sourced script".to_string()),
+        // Aliases and functions run commands under a name of their choice later on
+        "alias" | "shopt" => return Err("an alias definition".to_string()),
+        "function" => return Err("a function definition".to_string()),
+        // E.g. `rm -rf {}` in `find . -exec rm -rf {} +`
+        "find" => {
+            let mut rest = args.iter();
+            while rest.by_ref().any(|a| FIND_EXEC_ACTIONS.contains(&a.text.as_str())) {
+                let exec: Vec<Word> = rest.by_ref().take_while(|a| a.text != ";" && a.text != "+").cloned().collect();
+                commands.extend(simple_commands(exec, redirects)?);
+            }
+        }
+        _ => {}
+    }
+
+    Ok(commands)
+}
+
+/// The commands of the script given to a shell with `-c`
+///
+/// A shell running a script file, or reading its script from a redirected file, runs commands
+/// that can't be known, so that is an error. A script piped into the shell comes from commands
+/// that are checked themselves, e.g. `curl` in `curl x | sh`.
+fn shell_script(args: &[Word], redirects: bool) -> Result<Vec<SimpleCommand>, String> {
+    let mut rest = args.iter();
+    while let Some(arg) = rest.next() {
+        let text = arg.text.as_str();
+        if SHELL_VALUE_OPTIONS.contains(&text) {
+            rest.next();
+        } else if text == "-c" || (text.starts_with('-') && !text.starts_with("--") && text.ends_with('c')) {
+            return match rest.next() {
+                Some(script) => parse_command_line(&script.text),
+                None => Ok(Vec::new()),
+            };
+        } else if !text.starts_with(['-', '+']) || text == "-" || text == "--" {
+            return Err("a shell script file".to_string());
+        }
+    }
+    if redirects {
+        return Err("a shell script file".to_string());
+    }
+    Ok(Vec::new())
+}
+
+/// The command a wrapper runs, after the wrapper's options and their values
+fn wrapped_command(wrapper: &str, args: &[Word], redirects: bool) -> Result<Vec<SimpleCommand>, String> {
+    let value_options = WRAPPER_VALUE_OPTIONS
+        .iter()
+        .find(|(name, _)| *name == wrapper)
+        .map_or(&[][..], |(_, options)| *options);
+
+    let mut i = 0;
+    while let Some(arg) = args.get(i) {
+        let text = arg.text.as_str();
+        // `env -S 'curl -s x'` splits its value into the command
+        if wrapper == "env" {
+            let split = match text {
+                "-S" | "--split-string" => args.get(i + 1).map(|value| (value.text.as_str(), i + 2)),
+                _ => text
+                    .strip_prefix("--split-string=")
+                    .or_else(|| text.strip_prefix("-S").filter(|value| !value.is_empty()))
+                    .map(|value| (value, i + 1)),
+            };
+            if let Some((value, rest)) = split {
+                let line = format!("{} {}", value, join_words(args.get(rest..).unwrap_or_default()));
+                return parse_command_line(&line);
+            }
+        }
+        if text == "--" {
+            i += 1;
+            break;
+        }
+        if value_options.contains(&text) {
+            i += 2;
+        } else if text.starts_with('-') && text.len() > 1 {
+            i += 1;
+        } else {
+            break;
+        }
+    }
+
+    let mut inner = args.get(i..).unwrap_or_default().to_vec();
+    if wrapper == "timeout" && inner.first().is_some_and(|a| a.text.starts_with(|c: char| c.is_ascii_digit())) {
+        inner.remove(0);
+    }
+    simple_commands(inner, redirects)
+}
+
+fn join_words(words: &[Word]) -> String {
+    words.iter().map(|w| w.text.as_str()).collect::<Vec<_>>().join(" ")
+}
+
+fn is_assignment(word: &str) -> bool {
+    word.split_once('=')
+        .is_some_and(|(name, _)| !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_'))
+}
+
+/// Splits a command line into its simple commands
+///
+/// Commands are separated by `;`, `&&`, `||`, `|`, `&`, newlines and parentheses. Quotes,
+/// escapes, comments, redirections and heredoc bodies are handled like bash does. Command and
+/// process substitution (`$(...)`, backticks, `<(...)`), sourced scripts, shell script files and
+/// alias or function definitions can't be checked without running them and are reported as an
+/// error.
+pub fn parse_command_line(line: &str) -> Result<Vec<SimpleCommand>, String> {
+    let mut t = Tokenizer::default();
+    let mut chars = line.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            ' ' | '\t' => t.end_word(),
+            '\n' => {
+                t.end_command()?;
+                // Skip heredoc bodies up to their delimiter lines
+                for (delimiter, strip_tabs) in std::mem::take(&mut t.heredocs) {
+                    loop {
+                        let body_line: String = chars.by_ref().take_while(|&c| c != '\n').collect();
+                        let body_line = if strip_tabs { body_line.trim_start_matches('\t') } else { &body_line };
+                        if body_line == delimiter || chars.peek().is_none() {
+                            break;
+                        }
+                    }
+                }
+            }
+            // `name() { ...; }` defines a function, which runs its body under a new name; `x=()`
+            // is an empty array
+            '(' if chars.peek() == Some(&')') && !t.word.as_deref().is_some_and(|w| w.ends_with('=')) => {
+                return Err("a function definition".to_string());
+            }
+            ';' | '&' | '|' | '(' | ')' => t.end_command()?,
+            '<' | '>' => {
+                if chars.peek() == Some(&'(') {
+                    return Err("process substitution".to_string());
+                }
+                // A file descriptor number before the operator, e.g. `2>`
+                if t.word.as_deref().is_some_and(|w| w.chars().all(|c| c.is_ascii_digit())) {
+                    t.word = None;
+                } else {
+                    t.end_word();
+                }
+                t.redirects = true;
+                if c == '<' && chars.peek() == Some(&'<') {
+                    chars.next();
+                    if chars.peek() == Some(&'<') {
+                        // Here-string, the next word is the input
+                        chars.next();
+                        t.redirect_target = true;
+                    } else {
+                        let strip_tabs = chars.next_if_eq(&'-').is_some();
+                        t.heredocs.push((String::new(), strip_tabs));
+                        t.heredoc_pending = true;
+                    }
+                    continue;
+                }
+                chars.next_if(|&c| c == '>' || c == '&' || c == '|');
+                t.redirect_target = true;
+            }
+            '\'' => {
+                let word = t.word.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some('\'') => break,
+                        Some(c) => word.push(c),
+                        None => return Err("unterminated quote".to_string()),
+                    }
+                }
+            }
+            '"' => {
+                let word = t.word.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('`') => return Err("command substitution".to_string()),
+                        Some('$') if chars.peek() == Some(&'(') => return Err("command substitution".to_string()),
+                        Some('$') => {
+                            t.word_expands = true;
+                            word.push('$');
+                        }
+                        Some('\\') => match chars.next() {
+                            Some(c @ ('"' | '\\' | '$' | '`')) => word.push(c),
+                            Some('\n') => {}
+                            Some(c) => {
+                                word.push('\\');
+                                word.push(c);
+                            }
+                            None => return Err("unterminated quote".to_string()),
+                        },
+                        Some(c) => word.push(c),
+                        None => return Err("unterminated quote".to_string()),
+                    }
+                }
+            }
+            '\\' => match chars.next() {
+                Some('\n') | None => {}
+                Some(c) => t.word.get_or_insert_with(String::new).push(c),
+            },
+            '`' => return Err("command substitution".to_string()),
+            '$' if chars.peek() == Some(&'(') => return Err("command substitution".to_string()),
+            '#' if t.word.is_none() => {
+                while chars.next_if(|&c| c != '\n').is_some() {}
+            }
+            c => {
+                match c {
+                    '$' | '*' | '?' | '[' => t.word_expands = true,
+                    // `~` expands at the start of a word or after `=` or `:`, not in `HEAD~1`
+                    '~' if t.word.as_deref().is_none_or(|w| w.is_empty() || w.ends_with(['=', ':'])) => {
+                        t.word_expands = true
+                    }
+                    '{' | '}' => t.word_braces = true,
+                    _ => {}
+                }
+                t.word.get_or_insert_with(String::new).push(c);
+            }
+        }
+    }
+    t.end_command()?;
+
+    Ok(t.commands)
+}
+
+/// What happens to a command line
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "lowercase")]
+pub enum Action {
+    #[default]
+    Allow,
+    /// Refused with an explanation, so the agent can ask the user to run or allow it
+    Ask,
+    Deny,
+}
+
+impl fmt::Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Action::Allow => "allow",
+            Action::Ask => "ask",
+            Action::Deny => "deny",
+        })
+    }
+}
+
+/// Contents of a policy file
+///
+/// ```toml
+/// default = "ask"
+/// allow = ["cargo", "git diff", "git status", "ls"]
+/// ask = ["git push"]
+/// deny = ["rm -rf", "curl", "wget"]
+///
+/// [reasons]
+/// "curl" = "Network access goes through the crates.io tools"
+/// ```
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+struct PolicyFile {
+    #[serde(default)]
+    default: Action,
+    #[serde(default)]
+    allow: Vec<String>,
+    #[serde(default)]
+    ask: Vec<String>,
+    #[serde(default)]
+    deny: Vec<String>,
+    /// Explanations shown when a pattern refuses a command, by pattern
+    #[serde(default)]
+    reasons: BTreeMap<String, String>,
+}
+
+#[derive(Clone, Debug)]
+struct Rule {
+    pattern: String,
+    action: Action,
+    reason: Option<String>,
+}
+
+/// Allow, ask and deny rules for the commands `execute_bash` runs
+///
+/// A pattern's first word matches the program name; the following words match the
+/// command's first non-flag arguments in order, e.g. `git diff` matches `git --no-pager diff
+/// HEAD` but not `git push origin diff`. Flags in a pattern must be present anywhere, with
+/// combined short flags split up, so `rm -rf` matches `rm -fr x` and `rm -r -f x`. `*` and `?`
+/// are wildcards, and a trailing `*` word is allowed for readability (`cargo *`).
+///
+/// Values of global options are skipped, so `git push` matches `git -C dir push`, and long
+/// flags are matched by their short form, so `rm -rf` matches `rm --recursive --force`.
+///
+/// When several rules match, deny wins over ask and ask over allow. A command line is judged
+/// by its most restricted simple command, including commands run through `sudo`, `env`,
+/// `xargs`, `bash -c`, `eval`, `find -exec` and similar wrappers. A program name that is only
+/// known when the command runs, like `$X x`, is asked about, and deny and ask rules treat
+/// expanded arguments as matching anything.
+#[derive(Clone, Debug, Default)]
+pub struct CommandPolicy {
+    default: Action,
+    rules: Vec<Rule>,
+    /// File the policy was loaded from, mentioned in refusals
+    source: Option<PathBuf>,
+}
+
+/// The outcome of checking a command line against a policy
+#[derive(Debug)]
+pub struct Decision {
+    pub action: Action,
+    /// The simple command that decided the outcome
+    pub command: Option<String>,
+    /// The matching pattern, `None` when the default applied
+    pub pattern: Option<String>,
+    pub reason: Option<String>,
+}
+
+impl CommandPolicy {
+    /// Loads a policy from a TOML file
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let text = fs::read_to_string(path).map_err(|e| format!("could not read '{}': {}", path.display(), e))?;
+        let file: PolicyFile = toml::from_str(&text).map_err(|e| format!("invalid policy '{}': {}", path.display(), e))?;
+
+        let mut rules = Vec::new();
+        for (action, patterns) in [(Action::Allow, file.allow), (Action::Ask, file.ask), (Action::Deny, file.deny)] {
+            for pattern in patterns {
+                if pattern.split_whitespace().next().is_none() {
+                    return Err(format!("invalid policy '{}': empty {} pattern", path.display(), action));
+                }
+                rules.push(Rule {
+                    reason: file.reasons.get(&pattern).cloned(),
+                    pattern,
+                    action,
+                });
+            }
+        }
+
+        Ok(Self {
+            default: file.default,
+            rules,
+            source: Some(path.to_path_buf()),
+        })
+    }
+
+    pub fn source(&self) -> Option<&Path> {
+        self.source.as_deref()
+    }
+
+    /// Checks a whole command line
+    pub fn evaluate(&self, line: &str) -> Decision {
+        if self.rules.is_empty() && self.default == Action::Allow {
+            return Decision {
+                action: Action::Allow,
+                command: None,
+                pattern: None,
+                reason: None,
+            };
+        }
+
+        let commands = match parse_command_line(line) {
+            Ok(commands) => commands,
+            Err(construct) => {
+                return Decision {
+                    action: Action::Ask.max(self.default),
+                    command: None,
+                    pattern: None,
+                    reason: Some(format!("the command line uses {}, which can't be checked against the policy", construct)),
+                };
+            }
+        };
+
+        let mut decision = Decision {
+            action: Action::Allow,
+            command: None,
+            pattern: None,
+            reason: None,
+        };
+        for command in &commands {
+            let candidate = if command.program_word.expands {
+                Decision {
+                    action: Action::Ask.max(self.default),
+                    command: Some(command.to_string()),
+                    pattern: None,
+                    reason: Some(format!(
+                        "the program `{}` is only known when the command runs, so it can't be checked against the policy",
+                        command.program_word.text
+                    )),
+                }
+            } else {
+                let (action, rule) = self.evaluate_command(command);
+                Decision {
+                    action,
+                    command: Some(command.to_string()),
+                    pattern: rule.map(|r| r.pattern.clone()),
+                    reason: rule.and_then(|r| r.reason.clone()),
+                }
+            };
+            if candidate.action > decision.action || decision.command.is_none() {
+                decision = candidate;
+            }
+        }
+        decision
+    }
+
+    fn evaluate_command(&self, command: &SimpleCommand) -> (Action, Option<&Rule>) {
+        self.rules
+            .iter()
+            .filter(|rule| pattern_matches(&rule.pattern, command, rule.action != Action::Allow))
+            .max_by_key(|rule| rule.action)
+            .map_or((self.default, None), |rule| (rule.action, Some(rule)))
+    }
+}
+
+/// Splits `-rf` into `-r` and `-f`; long flags and single short flags are kept as is
+fn expand_flags(word: &str) -> Vec<String> {
+    match word.strip_prefix('-') {
+        Some(letters) if !letters.starts_with('-') && letters.len() > 1 && !letters.contains('=') => {
+            letters.chars().map(|c| format!("-{}", c)).collect()
+        }
+        _ => vec![word.to_string()],
+    }
+}
+
+/// Expands combined short flags and spells them the way patterns do, e.g. `--force` as `-f` for `rm`
+fn normalize_flags(program: &str, word: &str) -> Vec<String> {
+    expand_flags(word)
+        .into_iter()
+        .map(|flag| {
+            FLAG_ALIASES
+                .iter()
+                .find(|(name, alias, _)| *name == program && *alias == flag)
+                .map_or(flag, |(_, _, short)| short.to_string())
+        })
+        .collect()
+}
+
+/// Whether the pattern matches the command
+///
+/// With `loose`, used for deny and ask rules, a word the shell expands could turn into any flag
+/// or argument, so it matches any pattern word.
+fn pattern_matches(pattern: &str, command: &SimpleCommand, loose: bool) -> bool {
+    let mut words = pattern.split_whitespace();
+    let Some(program) = words.next() else {
+        return false;
+    };
+    if !wildcard_match(program, &command.program) {
+        return false;
+    }
+
+    let value_options = GLOBAL_VALUE_OPTIONS
+        .iter()
+        .find(|(name, _)| *name == command.program)
+        .map_or(&[][..], |(_, options)| *options);
+    let mut flags: Vec<String> = Vec::new();
+    let mut positional: Vec<&Word> = Vec::new();
+    let mut args = command.args.iter();
+    while let Some(arg) = args.next() {
+        if arg.text.starts_with('-') && arg.text.len() > 1 {
+            flags.extend(normalize_flags(&command.program, &arg.text));
+            // The value of an option before the subcommand, e.g. `.` in `git -C . push`
+            if positional.is_empty() && value_options.contains(&arg.text.as_str()) {
+                args.next();
+            }
+        } else {
+            positional.push(arg);
+        }
+    }
+    let any_flag = loose && command.args.iter().any(|a| a.expands);
+    let mut positional = positional.into_iter();
+
+    let words: Vec<&str> = words.collect();
+    let words = match words.split_last() {
+        Some((&"*", rest)) => rest,
+        _ => &words[..],
+    };
+    for word in words {
+        if word.starts_with('-') && word.len() > 1 {
+            let required = normalize_flags(&command.program, word);
+            if !any_flag && !required.iter().all(|r| flags.iter().any(|f| wildcard_match(r, f))) {
+                return false;
+            }
+        } else if !positional
+            .next()
+            .is_some_and(|arg| (loose && arg.expands) || wildcard_match(word, &arg.text))
+        {
+            return false;
+        }
+    }
+    true
+}
+
+/// Matches `*` (any run of characters) and `?` (one character)
+fn wildcard_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    let (mut p, mut t) = (0, 0);
+    let mut backtrack: Option<(usize, usize)> = None;
+
+    while t < text.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == text[t]) {
+            p += 1;
+            t += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            backtrack = Some((p, t));
+            p += 1;
+        } else if let Some((star, matched)) = backtrack {
+            p = star + 1;
+            t = matched + 1;
+            backtrack = Some((star, matched + 1));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+/// Renders the refusal for a command the policy doesn't allow
+pub fn render_refusal(policy: &CommandPolicy, decision: &Decision) -> String {
+    let mut out = match decision.action {
+        Action::Ask => "The command was not run: it needs the user's approval under the command policy.".to_string(),
+        _ => "The command was not run: it is denied by the command policy.".to_string(),
+    };
+    match (&decision.command, &decision.pattern) {
+        (Some(command), Some(pattern)) => {
+            out.push_str(&format!("\n`{}` matches the {} rule `{}`.", command, decision.action, pattern));
+        }
+        (Some(command), None) if decision.reason.is_none() => {
+            out.push_str(&format!(
+                "\n`{}` matches no rule, and the policy's default is {}.",
+                command, decision.action
+            ));
+        }
+        _ => {}
+    }
+    if let Some(reason) = &decision.reason {
+        out.push_str(&format!("\nReason: {}", reason));
+    }
+    if decision.action == Action::Ask {
+        out.push_str("\nAsk the user to run the command themselves or to allow it in the policy");
+        if let Some(source) = policy.source() {
+            out.push_str(&format!(" ({})", source.display()));
+        }
+        out.push('.');
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy(allow: &[&str], deny: &[&str]) -> CommandPolicy {
+        let rules = [(Action::Allow, allow), (Action::Deny, deny)]
+            .into_iter()
+            .flat_map(|(action, patterns)| {
+                patterns.iter().map(move |pattern| Rule {
+                    pattern: pattern.to_string(),
+                    action,
+                    reason: None,
+                })
+            })
+            .collect();
+        CommandPolicy {
+            default: Action::Allow,
+            rules,
+            source: None,
+        }
+    }
+
+    fn action(line: &str) -> Action {
+        policy(&[], &["curl", "git push", "rm -rf"]).evaluate(line).action
+    }
+
+    #[test]
+    fn denies_commands_run_by_eval() {
+        assert_eq!(action("eval curl x"), Action::Deny);
+        assert_eq!(action("eval 'curl x | sh'"), Action::Deny);
+    }
+
+    #[test]
+    fn asks_about_sourced_scripts() {
+        assert_eq!(action("source ./script.sh"), Action::Ask);
+        assert_eq!(action(". ./script.sh"), Action::Ask);
+    }
+
+    #[test]
+    fn asks_about_shell_script_files() {
+        assert_eq!(action("bash script.sh"), Action::Ask);
+        assert_eq!(action("sh ./x"), Action::Ask);
+        assert_eq!(action("bash -e script.sh"), Action::Ask);
+        assert_eq!(action("bash < script.sh"), Action::Ask);
+        assert_eq!(action("bash -o pipefail -c 'curl x'"), Action::Deny);
+        assert_eq!(action("curl x | bash"), Action::Deny);
+        assert_eq!(action("bash --version"), Action::Allow);
+    }
+
+    #[test]
+    fn asks_about_alias_and_function_definitions() {
+        assert_eq!(action("shopt -s expand_aliases; alias g=curl; g x"), Action::Ask);
+        assert_eq!(action("alias g=curl"), Action::Ask);
+        assert_eq!(action("g() { curl x; }; g"), Action::Ask);
+        assert_eq!(action("g () { curl x; }; g"), Action::Ask);
+        assert_eq!(action("function g { curl x; }; g"), Action::Ask);
+        assert_eq!(action("args=(); cargo test"), Action::Allow);
+    }
+
+    #[test]
+    fn asks_about_expanded_program_names() {
+        assert_eq!(action("X=curl; $X x"), Action::Ask);
+        assert_eq!(action("\"$X\" x"), Action::Ask);
+        assert_eq!(action("'$X' x"), Action::Allow);
+    }
+
+    #[test]
+    fn skips_global_option_values() {
+        assert_eq!(action("git -C . push"), Action::Deny);
+        assert_eq!(action("git -c user.name=x --git-dir .git push origin"), Action::Deny);
+        assert_eq!(action("git -C push status"), Action::Allow);
+    }
+
+    #[test]
+    fn normalizes_long_and_combined_flags() {
+        assert_eq!(action("rm --recursive --force /"), Action::Deny);
+        assert_eq!(action("rm -R --force /"), Action::Deny);
+        assert_eq!(action("rm -fr /"), Action::Deny);
+        assert_eq!(action("rm --force x"), Action::Allow);
+    }
+
+    #[test]
+    fn denies_commands_run_by_find_and_xargs() {
+        assert_eq!(action("find . -exec rm -rf {} +"), Action::Deny);
+        assert_eq!(action("find . -name x -execdir curl {} \\;"), Action::Deny);
+        assert_eq!(action("find . -name '*.rs' | xargs -n 1 rm -rf"), Action::Deny);
+        assert_eq!(action("xargs -I {} curl {}"), Action::Deny);
+    }
+
+    #[test]
+    fn denies_commands_run_by_wrappers() {
+        assert_eq!(action("sudo -u root rm -rf /"), Action::Deny);
+        assert_eq!(action("env -S 'curl x'"), Action::Deny);
+        assert_eq!(action("timeout -s KILL 5 curl x"), Action::Deny);
+        assert_eq!(action("bash -c 'curl x'"), Action::Deny);
+    }
+
+    #[test]
+    fn expanded_arguments_match_deny_rules_but_not_allow_rules() {
+        assert_eq!(action("git $SUBCOMMAND origin"), Action::Deny);
+        assert_eq!(action("rm $FLAGS /"), Action::Deny);
+
+        let allow_status = CommandPolicy {
+            default: Action::Ask,
+            ..policy(&["git status"], &[])
+        };
+        assert_eq!(allow_status.evaluate("git status").action, Action::Allow);
+        assert_eq!(allow_status.evaluate("git $X").action, Action::Ask);
+    }
+}
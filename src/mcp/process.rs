@@ -0,0 +1,119 @@
+use std::collections::VecDeque;
+use std::io;
+use std::process::{Output, Stdio};
+use std::time::Duration;
+
+use tokio::process::Command;
+
+/// Default time a command may run before it is killed
+pub const DEFAULT_COMMAND_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// Default number of bytes kept from each of stdout and stderr
+pub const DEFAULT_MAX_OUTPUT_BYTES: usize = 30_000;
+
+/// Keeps the first and last `limit / 2` bytes of a stream and counts what was dropped
+pub(crate) struct CappedBuffer {
+    head: Vec<u8>,
+    tail: VecDeque<u8>,
+    half: usize,
+    total: usize,
+}
+
+impl CappedBuffer {
+    pub(crate) fn new(limit: usize) -> Self {
+        Self {
+            head: Vec::new(),
+            tail: VecDeque::new(),
+            half: limit / 2,
+            total: 0,
+        }
+    }
+
+    pub(crate) fn push(&mut self, bytes: &[u8]) {
+        self.total += bytes.len();
+        let mut rest = bytes;
+
+        if self.head.len() < self.half {
+            let take = rest.len().min(self.half - self.head.len());
+            self.head.extend_from_slice(&rest[..take]);
+            rest = &rest[take..];
+        }
+
+        self.tail.extend(rest);
+        let overflow = self.tail.len().saturating_sub(self.half);
+        self.tail.drain(..overflow);
+    }
+
+    /// Returns the kept text with a truncation marker, and the number of dropped bytes
+    pub(crate) fn finish(&mut self) -> (String, usize) {
+        let dropped = self.total - self.head.len() - self.tail.len();
+        let head = std::mem::take(&mut self.head);
+        let tail: Vec<u8> = self.tail.drain(..).collect();
+
+        if dropped == 0 {
+            let mut bytes = head;
+            bytes.extend_from_slice(&tail);
+            return (String::from_utf8_lossy(&bytes).to_string(), 0);
+        }
+
+        let text = format!(
+            "{}\n\n[... {} bytes truncated ...]\n\n{}",
+            String::from_utf8_lossy(&head),
+            dropped,
+            String::from_utf8_lossy(&tail)
+        );
+        (text, dropped)
+    }
+}
+
+/// Runs the command in its own process group and collects its output
+///
+/// Returns `None` if the command runs longer than `timeout`, in which case the whole group is
+/// killed, so e.g. test binaries started by `cargo test` don't outlive it.
+pub(crate) async fn output_with_timeout(mut command: Command, timeout: Duration) -> io::Result<Option<Output>> {
+    command
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true);
+    #[cfg(unix)]
+    command.process_group(0);
+
+    let child = command.spawn()?;
+    let pid = child.id();
+    match tokio::time::timeout(timeout, child.wait_with_output()).await {
+        Ok(output) => output.map(Some),
+        Err(_) => {
+            if let Some(pid) = pid {
+                kill_process_group(pid);
+            }
+            Ok(None)
+        }
+    }
+}
+
+/// Kills the whole process group so children of the shell (e.g. `cargo run`) die too
+#[cfg(unix)]
+pub(crate) fn kill_process_group(pid: u32) {
+    signal_process_group(pid, libc::SIGKILL);
+}
+
+/// Asks the whole process group to shut down with SIGTERM
+#[cfg(unix)]
+pub(crate) fn terminate_process_group(pid: u32) {
+    signal_process_group(pid, libc::SIGTERM);
+}
+
+#[cfg(unix)]
+fn signal_process_group(pid: u32, signal: libc::c_int) {
+    // SAFETY: kill has no memory safety requirements; a negative pid targets the group
+    unsafe {
+        libc::kill(-(pid as i32), signal);
+    }
+}
+
+#[cfg(not(unix))]
+pub(crate) fn kill_process_group(_pid: u32) {}
+
+#[cfg(not(unix))]
+pub(crate) fn terminate_process_group(_pid: u32) {}
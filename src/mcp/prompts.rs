@@ -7,7 +7,8 @@ pub const CODE_CHANGE_WORKFLOW: &str = r#"
 ## Basic Change Loop
 1. READ: Understand the current code with `read_file`
 2. MODIFY: Make the requested changes with `edit_file` or `write_file`
-3. VERIFY: Run `check_code` to ensure changes compile
+3. VERIFY: Run `check_code` to ensure changes compile, then `check_code` with
+   `"command": "clippy"`, `"test"` and `"fmt"` for lints, test compilation and formatting
 4. ITERATE: If verification fails, fix issues and return to step 3
 5. COMPLETE: When verification passes, report success
 
@@ -33,7 +34,8 @@ When making changes to Rust code, follow the workflow below.
 ## Basic Change Loop
 1. READ: Understand the current code with `read_file`
 2. MODIFY: Make the requested changes with `edit_file` or `write_file`
-3. VERIFY: Run `check_code` to ensure changes compile
+3. VERIFY: Run `check_code` to ensure changes compile, then `check_code` with
+   `"command": "clippy"`, `"test"` and `"fmt"` for lints, test compilation and formatting
 4. ITERATE: If verification fails, fix issues and return to step 3
 5. COMPLETE: When verification passes, report success
 
@@ -47,23 +49,39 @@ When adding a new dependency, insert this between steps 1 and 2:
 
 1. `read_file`: Read content from a file
    - Usage: `read_file({ "file_path": "path/to/file" })`
+   - Lines 120-180 with line numbers: `read_file({ "file_path": "path/to/file", "start_line": 120, "end_line": 180, "line_numbers": true })`
+   - Large files are returned a page at a time; continue from the `start_line` named at the end of the output
+   - Binary files return their size, detected type and a hex preview instead of content
    - Best practice: Check if file exists before reading
 
 2. `write_file`: Write or overwrite content to a file
    - Usage: `write_file({ "file_path": "path/to/file", "content": "file content" })`
    - Always verify writes with check_code if modifying Rust code
 
-3. `edit_file`: Modify a file using unified diffs
-   - Usage: `edit_file({ "file_path": "path/to/file", "diff": "@@ ... @@" })`
-   - Prefer for small, targeted changes to maintain context
+3. `edit_file`: Replace an exact string in a file
+   - Usage: `edit_file({ "file_path": "path/to/file", "old_string": "let x = 1;", "new_string": "let x = 2;" })`
+   - `old_string` must match exactly, including indentation, and be unique; add surrounding lines or set `"replace_all": true`
+   - Prefer for small, targeted changes; the response shows the edited lines in context
+   - For changes spanning several files, `patch_file` takes a unified diff like `git diff` prints
+   - Usage: `patch_file({ "patch": "--- a/src/lib.rs\n+++ b/src/lib.rs\n@@ ... @@\n..." })`; hunk line numbers are corrected automatically
+   - Hunks that differ from the file only in trailing whitespace or indentation still match; added lines are re-indented to fit
+   - A hunk whose context lines are slightly off still applies where they closely resemble the file, unless several places do; the report then lists them
+   - A multi-file patch is applied to every file or to none
+   - Create files with `--- /dev/null`, delete them with `+++ /dev/null`, and move them with `rename from`/`rename to` lines as `git diff -M` prints
+   - Add `"dry_run": true` to see where each hunk matches and the resulting diff without changing files
+   - If a hunk fails, nothing is applied and the report shows the closest region and first differing line; `"partial": true` applies the hunks that match
 
 4. `check_code`: Verify that Rust code compiles correctly
-   - Usage: `check_code({})`
+   - Usage: `check_code({})` or `check_code({ "command": "clippy", "package": "my-crate" })`
+   - Commands: check (default), clippy, build, test (compile only), fmt (check only), doc
+   - Supports `package`, `workspace`, `features`, `all_features`, `no_default_features`, `target` and `profile`
    - Always run after any code modifications
 
 5. `execute_bash`: Run shell commands with proper context
    - Usage: `execute_bash({ "command": "ls -la" })`
    - Use to run cargo commands, navigate directories, etc.
+   - Runs in a persistent shell: exported variables, `cd`, `source .env`, functions and virtualenvs carry over between calls
+   - Pass `"session": "name"` to use a separate shell with its own environment and directory
 
 6. `tool_search_crates`: Search for packages on crates.io
    - Usage: `tool_search_crates({ "query": "tokio", "page": 1, "per_page": 10 })`
@@ -84,4 +102,43 @@ When adding a new dependency, insert this between steps 1 and 2:
 10. `list_function_signatures`: List function signatures in the project
     - Usage: `list_function_signatures({ "file_path": null })`
     - Use to understand the project structure
+
+11. `project_outline`: Outline the structure of a file or directory
+    - Usage: `project_outline({ "path": "src" })`
+    - Lists functions, structs, enums, traits, impls and imports with line ranges
+    - Use to orient yourself in a large workspace before reading files
+
+12. `apply_suggestions`: Apply the compiler's machine-applicable fixes
+    - Usage: `apply_suggestions({ "clippy": false })`
+    - Applies rustc/clippy suggestions marked MachineApplicable and reports skipped overlaps
+    - Run `check_code` afterwards to see what remains
+
+13. `run_tests`: Run `cargo test` and get per-test results
+    - Usage: `run_tests({ "filter": "parser" })` or `run_tests({ "rerun_failed": true })`
+    - Reports passed/failed/ignored tests with panic messages and captured output of failures
+    - Per-test durations are only reported on nightly toolchains
+    - Accepts the same package and feature options as `check_code`
+
+14. `reset_shell`: Reset a persistent shell session used by `execute_bash`
+    - Usage: `reset_shell({})` or `reset_shell({ "session": "name" })`
+    - Use when the shell's environment got into a bad state; the next command starts fresh in the server's working directory
+
+15. `start_job`, `list_jobs`, `read_job_output`, `write_job_stdin`, `kill_job`: Run commands in the background
+    - Usage: `start_job({ "command": "cargo build --release" })`, then `read_job_output({ "job_id": 1, "wait_secs": 10 })`
+    - Use for dev servers, watchers and long builds while continuing with other work
+    - `read_job_output` returns only the output produced since the previous read
+    - `kill_job` sends SIGTERM, kills the job after a few seconds, and returns its remaining output
+
+16. `list_directory`: Show a directory as a tree with file sizes and counts
+    - Usage: `list_directory({ "path": "crates", "depth": 3 })`
+    - Respects `.gitignore` and `.ignore`, and skips `target/` and version control directories
+
+17. `search_code`: Search file contents with a regex or literal text
+    - Usage: `search_code({ "pattern": "fn parse_\\w+", "include": ["*.rs"], "context_lines": 2 })`
+    - Literal, case-insensitive: `search_code({ "pattern": "todo!(", "literal": true, "case_sensitive": false })`
+    - Returns `path:line:column` matches; prefer it over `grep` in `execute_bash`
+
+18. `find_files`: Find files by glob pattern, most recently modified first
+    - Usage: `find_files({ "patterns": ["**/Cargo.toml"] })` or `find_files({ "patterns": ["src/**/*.rs"], "path": "crates/core" })`
+    - `*` doesn't cross directories; use `**/` to match at any depth
 "#;
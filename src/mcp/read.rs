@@ -0,0 +1,264 @@
+/// Largest amount of file content returned by one `read_file` call
+pub const MAX_READ_BYTES: usize = 100_000;
+
+/// Bytes inspected when deciding whether a file is binary
+const SNIFF_BYTES: usize = 8192;
+
+/// Bytes shown in the hex preview of a binary file
+const HEX_PREVIEW_BYTES: usize = 256;
+
+/// Magic numbers of common binary formats, checked in order
+const SIGNATURES: &[(&[u8], &str)] = &[
+    (b"\x89PNG\r\n\x1a\n", "PNG image"),
+    (b"\xff\xd8\xff", "JPEG image"),
+    (b"GIF87a", "GIF image"),
+    (b"GIF89a", "GIF image"),
+    (b"%PDF-", "PDF document"),
+    (b"PK\x03\x04", "ZIP archive (also jar, docx, xlsx, ...)"),
+    (b"\x1f\x8b", "gzip compressed data"),
+    (b"BZh", "bzip2 compressed data"),
+    (b"\xfd7zXZ\x00", "xz compressed data"),
+    (b"\x28\xb5\x2f\xfd", "Zstandard compressed data"),
+    (b"7z\xbc\xaf\x27\x1c", "7-Zip archive"),
+    (b"\x7fELF", "ELF executable or object file"),
+    (b"\xcf\xfa\xed\xfe", "Mach-O executable (64-bit)"),
+    (b"\xce\xfa\xed\xfe", "Mach-O executable (32-bit)"),
+    (b"\xca\xfe\xba\xbe", "Mach-O universal binary or Java class file"),
+    (b"MZ", "Windows (PE) executable or DLL"),
+    (b"\x00asm", "WebAssembly module"),
+    (b"SQLite format 3\x00", "SQLite database"),
+    (b"!<arch>\n", "ar archive (e.g. a static library .a/.rlib)"),
+    (b"\x00\x00\x01\x00", "ICO image"),
+    (b"OggS", "Ogg media"),
+    (b"fLaC", "FLAC audio"),
+    (b"ID3", "MP3 audio"),
+    (b"wOFF", "WOFF font"),
+    (b"wOF2", "WOFF2 font"),
+];
+
+/// File content as `read_file` presents it
+pub enum Decoded {
+    Text {
+        content: String,
+        /// Explains how the content was decoded when it wasn't plain UTF-8
+        note: Option<String>,
+    },
+    Binary,
+}
+
+/// Decodes UTF-8 (with or without a byte order mark), UTF-16 with a byte order mark and
+/// Latin-1 text; anything that looks like binary data is reported as such
+pub fn decode(bytes: &[u8]) -> Decoded {
+    if let Some(rest) = bytes.strip_prefix(b"\xef\xbb\xbf")
+        && let Ok(content) = std::str::from_utf8(rest)
+    {
+        return Decoded::Text { content: content.to_string(), note: None };
+    }
+    for (bom, name, from_bytes) in [
+        (b"\xff\xfe", "UTF-16LE", u16::from_le_bytes as fn([u8; 2]) -> u16),
+        (b"\xfe\xff", "UTF-16BE", u16::from_be_bytes),
+    ] {
+        if let Some(rest) = bytes.strip_prefix(bom) {
+            if rest.len() % 2 != 0 {
+                return Decoded::Binary;
+            }
+            let units = rest.chunks_exact(2).map(|pair| from_bytes([pair[0], pair[1]]));
+            return match char::decode_utf16(units).collect::<Result<String, _>>() {
+                Ok(content) => Decoded::Text {
+                    content,
+                    note: Some(format!("Decoded from {} (byte order mark found). Writing the file replaces it with UTF-8.", name)),
+                },
+                Err(_) => Decoded::Binary,
+            };
+        }
+    }
+
+    if looks_binary(&bytes[..bytes.len().min(SNIFF_BYTES)]) {
+        return Decoded::Binary;
+    }
+    match std::str::from_utf8(bytes) {
+        Ok(content) => Decoded::Text { content: content.to_string(), note: None },
+        Err(_) => Decoded::Text {
+            // Every byte is a valid Latin-1 character, mapping to the code point of the same value
+            content: bytes.iter().map(|&b| b as char).collect(),
+            note: Some("Not valid UTF-8; decoded as Latin-1 (ISO-8859-1). Writing the file replaces it with UTF-8.".to_string()),
+        },
+    }
+}
+
+/// NUL bytes, or many control characters other than whitespace and escape, mean binary data
+fn looks_binary(sample: &[u8]) -> bool {
+    if sample.contains(&0) {
+        return true;
+    }
+    let control = sample
+        .iter()
+        .filter(|&&b| b < 0x20 && !matches!(b, b'\t' | b'\n' | b'\r' | 0x0c | 0x1b))
+        .count();
+    control * 10 > sample.len()
+}
+
+/// Names the format of binary data from its magic number
+fn detect_type(bytes: &[u8]) -> &'static str {
+    if bytes.len() > 262 && &bytes[257..262] == b"ustar" {
+        return "tar archive";
+    }
+    if bytes.starts_with(b"RIFF") && bytes.len() >= 12 {
+        return match &bytes[8..12] {
+            b"WAVE" => "WAV audio",
+            b"WEBP" => "WebP image",
+            b"AVI " => "AVI video",
+            _ => "RIFF container",
+        };
+    }
+    SIGNATURES
+        .iter()
+        .find(|(magic, _)| bytes.starts_with(magic))
+        .map_or("unknown binary data", |(_, name)| name)
+}
+
+/// Describes a binary file: its size, detected type and a hex dump of its first bytes
+pub fn describe_binary(path: &str, bytes: &[u8]) -> String {
+    let mut out = format!(
+        "Binary file: {}\nSize: {} bytes\nDetected type: {}\n\nFirst {} bytes:\n",
+        path,
+        bytes.len(),
+        detect_type(bytes),
+        bytes.len().min(HEX_PREVIEW_BYTES)
+    );
+    for (row, chunk) in bytes[..bytes.len().min(HEX_PREVIEW_BYTES)].chunks(16).enumerate() {
+        let hex: Vec<String> = chunk.iter().map(|b| format!("{:02x}", b)).collect();
+        let ascii: String = chunk
+            .iter()
+            .map(|&b| if b.is_ascii_graphic() || b == b' ' { b as char } else { '.' })
+            .collect();
+        out.push_str(&format!("{:08x}  {:<47}  |{}|\n", row * 16, hex.join(" "), ascii));
+    }
+    out
+}
+
+/// Options selecting which part of a file `read_file` returns
+pub struct ReadOptions {
+    /// First line to return, 1-based
+    pub start_line: Option<usize>,
+    /// Last line to return, inclusive
+    pub end_line: Option<usize>,
+    /// Prefix each line with its number
+    pub line_numbers: bool,
+    pub max_bytes: usize,
+}
+
+/// Renders the requested lines of a file
+///
+/// When the lines don't fit in `max_bytes`, the output stops at the last whole line that fits
+/// and ends with a marker naming the `start_line` to continue from.
+pub fn render_lines(content: &str, options: &ReadOptions) -> Result<String, String> {
+    let lines: Vec<&str> = content.split_inclusive('\n').collect();
+    let total = lines.len();
+
+    let start = options.start_line.unwrap_or(1);
+    if start == 0 {
+        return Err("start_line is 1-based and must be at least 1".to_string());
+    }
+    let end = options.end_line.unwrap_or(total).min(total);
+    if let Some(end_line) = options.end_line
+        && end_line < start
+    {
+        return Err(format!("end_line ({}) is before start_line ({})", end_line, start));
+    }
+    if start > total.max(1) {
+        return Err(format!("start_line ({}) is past the end of the file, which has {} lines", start, total));
+    }
+
+    let width = end.to_string().len();
+    let mut out = String::new();
+    let mut last = start.saturating_sub(1);
+    for (number, line) in lines.iter().enumerate().take(end).skip(start - 1).map(|(i, line)| (i + 1, line)) {
+        let prefix = if options.line_numbers {
+            format!("{:>width$}\t", number)
+        } else {
+            String::new()
+        };
+        if out.len() + prefix.len() + line.len() > options.max_bytes {
+            if number == start {
+                // A single line larger than the limit; return its beginning rather than nothing
+                let mut cut = options.max_bytes.saturating_sub(prefix.len());
+                while !line.is_char_boundary(cut) {
+                    cut -= 1;
+                }
+                out.push_str(&prefix);
+                out.push_str(&line[..cut]);
+                out.push_str(&format!("\n[... line {} is {} bytes long and was truncated ...]\n", number, line.len()));
+                last = number;
+            }
+            break;
+        }
+        out.push_str(&prefix);
+        out.push_str(line);
+        last = number;
+    }
+
+    if last < end {
+        if !out.ends_with('\n') {
+            out.push('\n');
+        }
+        out.push_str(&format!(
+            "\n[... more available: showed lines {}-{} of {}. Call read_file with start_line={} to continue ...]\n",
+            start,
+            last,
+            total,
+            last + 1
+        ));
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(bytes: &[u8]) -> (String, Option<String>) {
+        match decode(bytes) {
+            Decoded::Text { content, note } => (content, note),
+            Decoded::Binary => panic!("decoded as binary"),
+        }
+    }
+
+    #[test]
+    fn strips_the_utf8_byte_order_mark() {
+        assert_eq!(text(b"\xef\xbb\xbffn main() {}\n"), ("fn main() {}\n".to_string(), None));
+        assert_eq!(text("héllo\n".as_bytes()), ("héllo\n".to_string(), None));
+    }
+
+    #[test]
+    fn decodes_utf16_with_a_byte_order_mark() {
+        let (content, note) = text(b"\xff\xfeh\x00\xe9\x00\n\x00");
+        assert_eq!(content, "hé\n");
+        assert!(note.unwrap().contains("UTF-16LE"));
+
+        let (content, note) = text(b"\xfe\xff\x00h\x00\xe9\x00\n");
+        assert_eq!(content, "hé\n");
+        assert!(note.unwrap().contains("UTF-16BE"));
+    }
+
+    #[test]
+    fn reports_truncated_or_unpaired_utf16_as_binary() {
+        assert!(matches!(decode(b"\xff\xfeh\x00\xe9"), Decoded::Binary));
+        assert!(matches!(decode(b"\xff\xfe\x00\xd8h\x00"), Decoded::Binary));
+    }
+
+    #[test]
+    fn falls_back_to_latin1_for_invalid_utf8() {
+        let (content, note) = text(b"caf\xe9 cr\xe8me\n");
+        assert_eq!(content, "café crème\n");
+        assert!(note.unwrap().contains("Latin-1"));
+    }
+
+    #[test]
+    fn detects_binary_data() {
+        assert!(matches!(decode(b"\x89PNG\r\n\x1a\n\x00\x00\x00\rIHDR"), Decoded::Binary));
+        assert!(matches!(decode(&[0x01, 0x02, 0x03, 0x04, b'a', b'b']), Decoded::Binary));
+        assert!(matches!(decode(b"\x1b[31mred\x1b[0m\tand\x0cmore\n"), Decoded::Text { .. }));
+        assert_eq!(detect_type(b"\x7fELF\x02\x01\x01"), "ELF executable or object file");
+    }
+}
@@ -0,0 +1,186 @@
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+
+/// Symlinks followed while resolving a path that doesn't exist yet, like the kernel's limit
+const MAX_SYMLINK_HOPS: usize = 40;
+
+/// Directories the file tools may access; everything else is refused
+///
+/// Roots are stored canonicalized, and paths are canonicalized (following symlinks) before
+/// they are compared, so a symlink inside a root that points outside of it is refused too.
+#[derive(Clone, Debug)]
+pub struct AllowedRoots(Vec<PathBuf>);
+
+/// A path that resolved to a location outside the allowed roots
+#[derive(Debug)]
+pub struct OutsideRoots {
+    pub path: PathBuf,
+    pub resolved: PathBuf,
+    pub roots: Vec<PathBuf>,
+}
+
+impl fmt::Display for OutsideRoots {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let roots: Vec<String> = self.roots.iter().map(|r| r.display().to_string()).collect();
+        write!(f, "Permission denied: '{}'", self.path.display())?;
+        if self.resolved != self.path {
+            write!(f, " resolves to '{}', which", self.resolved.display())?;
+        }
+        write!(
+            f,
+            " is outside the allowed roots ({}). Start the server with --allow-root to permit other directories.",
+            roots.join(", ")
+        )
+    }
+}
+
+impl AllowedRoots {
+    /// Canonicalizes the given roots; each must be an existing directory
+    pub fn new(roots: impl IntoIterator<Item = PathBuf>) -> io::Result<Self> {
+        let mut canonical = Vec::new();
+        for root in roots {
+            let path = fs::canonicalize(&root)
+                .map_err(|e| io::Error::new(e.kind(), format!("allowed root '{}': {}", root.display(), e)))?;
+            if !path.is_dir() {
+                return Err(io::Error::new(
+                    io::ErrorKind::NotADirectory,
+                    format!("allowed root '{}' is not a directory", root.display()),
+                ));
+            }
+            canonical.push(path);
+        }
+        Ok(Self(canonical))
+    }
+
+    pub fn roots(&self) -> &[PathBuf] {
+        &self.0
+    }
+
+    /// Returns the canonical form of `path` if it lies within one of the roots
+    ///
+    /// `path` doesn't have to exist, so files about to be created can be checked too.
+    pub fn check(&self, path: &Path) -> Result<PathBuf, OutsideRoots> {
+        let outside = |resolved: PathBuf| OutsideRoots {
+            path: path.to_path_buf(),
+            resolved,
+            roots: self.0.clone(),
+        };
+        let Some(resolved) = canonicalize_lenient(path) else {
+            return Err(outside(path.to_path_buf()));
+        };
+        if self.0.iter().any(|root| resolved.starts_with(root)) {
+            Ok(resolved)
+        } else {
+            Err(outside(resolved))
+        }
+    }
+}
+
+/// Canonicalizes a path whose last components may not exist yet
+///
+/// The longest existing prefix is canonicalized and the missing components are appended. A `..`
+/// after a missing component leads back to an existing directory, so the components after it
+/// are resolved on the filesystem again, following any symlinks among them. A dangling symlink
+/// is resolved to its target, so creating a file through it can't escape the roots. Returns
+/// `None` for relative paths and symlink loops.
+fn canonicalize_lenient(path: &Path) -> Option<PathBuf> {
+    let mut path = path.to_path_buf();
+    for _ in 0..MAX_SYMLINK_HOPS {
+        if !path.is_absolute() {
+            return None;
+        }
+        if let Ok(canonical) = fs::canonicalize(&path) {
+            return Some(canonical);
+        }
+
+        // Walk up to the first ancestor that exists, remembering the missing components
+        let mut existing = path.as_path();
+        let mut missing = Vec::new();
+        while fs::symlink_metadata(existing).is_err() {
+            // `file_name` is `None` for a trailing `..`, which is applied after canonicalizing
+            missing.push(existing.file_name().map_or_else(|| "..".into(), |name| name.to_os_string()));
+            existing = existing.parent()?;
+        }
+
+        // A dangling symlink: continue with its target
+        if fs::symlink_metadata(existing).is_ok_and(|m| m.file_type().is_symlink())
+            && fs::canonicalize(existing).is_err()
+        {
+            let target = fs::read_link(existing).ok()?;
+            let mut next = existing.parent()?.join(target);
+            next.extend(missing.iter().rev());
+            path = next;
+            continue;
+        }
+
+        let mut resolved = fs::canonicalize(existing).ok()?;
+        let mut components = missing.iter().rev().flat_map(|c| Path::new(c).components());
+        while let Some(component) = components.next() {
+            match component {
+                Component::ParentDir => {
+                    resolved.pop();
+                    let rest: PathBuf = components.collect();
+                    if rest.as_os_str().is_empty() {
+                        return Some(resolved);
+                    }
+                    return canonicalize_lenient(&resolved.join(rest));
+                }
+                Component::Normal(name) => resolved.push(name),
+                _ => {}
+            }
+        }
+        return Some(resolved);
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A fresh directory below the system temp directory, removed first if it exists
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("corrode-roots-{}-{}", std::process::id(), name));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        fs::canonicalize(dir).unwrap()
+    }
+
+    #[test]
+    fn allows_existing_and_missing_paths_inside_the_root() {
+        let root = temp_dir("inside");
+        fs::create_dir(root.join("src")).unwrap();
+        let roots = AllowedRoots::new([root.clone()]).unwrap();
+
+        assert_eq!(roots.check(&root.join("src")).unwrap(), root.join("src"));
+        assert_eq!(roots.check(&root.join("src/new/file.rs")).unwrap(), root.join("src/new/file.rs"));
+        assert_eq!(roots.check(&root.join("missing/../src/x.rs")).unwrap(), root.join("src/x.rs"));
+    }
+
+    #[test]
+    fn refuses_paths_outside_the_root() {
+        let root = temp_dir("outside");
+        let roots = AllowedRoots::new([root.clone()]).unwrap();
+
+        assert!(roots.check(&root.join("../elsewhere.txt")).is_err());
+        assert!(roots.check(&root.join("missing/../../elsewhere.txt")).is_err());
+        assert!(roots.check(Path::new("relative.txt")).is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn refuses_symlinks_that_escape_the_root() {
+        let root = temp_dir("symlink-root");
+        let outside = temp_dir("symlink-outside");
+        std::os::unix::fs::symlink(&outside, root.join("link")).unwrap();
+        std::os::unix::fs::symlink(outside.join("missing.txt"), root.join("dangling")).unwrap();
+        let roots = AllowedRoots::new([root.clone()]).unwrap();
+
+        assert!(roots.check(&root.join("link/new.txt")).is_err());
+        assert!(roots.check(&root.join("missing/../link/new.txt")).is_err());
+        assert!(roots.check(&root.join("a/b/../../link/new.txt")).is_err());
+        assert!(roots.check(&root.join("dangling")).is_err());
+    }
+}
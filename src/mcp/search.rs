@@ -0,0 +1,173 @@
+use std::fmt::Write as _;
+use std::fs;
+use std::path::Path;
+
+use globset::GlobSet;
+use regex::Regex;
+use serde::Serialize;
+
+use crate::mcp::read::{self, Decoded};
+use crate::mcp::walk::{self, FilteredWalk};
+
+/// Matching lines returned when `search_code` isn't given a limit
+pub const DEFAULT_MAX_RESULTS: usize = 100;
+
+/// Longest line shown in results; longer lines, e.g. minified files, are cut
+const MAX_LINE_CHARS: usize = 300;
+
+#[derive(Serialize)]
+pub struct SearchMatch {
+    pub path: String,
+    /// 1-based line number
+    pub line: usize,
+    /// 1-based column of the first match on the line, in characters
+    pub column: usize,
+    pub text: String,
+}
+
+pub struct SearchOptions {
+    pub regex: Regex,
+    /// Only files whose path relative to the search root matches one of these are searched
+    pub include: Option<GlobSet>,
+    /// Files whose path relative to the search root matches one of these are skipped
+    pub exclude: Option<GlobSet>,
+    pub context_lines: usize,
+    pub max_results: usize,
+}
+
+pub struct SearchResults {
+    pub matches: Vec<SearchMatch>,
+    /// Matches with their context lines, grouped per file like `grep -n`
+    pub rendered: String,
+    pub files_searched: usize,
+    /// The search stopped at `max_results`
+    pub truncated: bool,
+}
+
+/// Builds a glob set from patterns like `*.rs` or `src/**/*.toml`
+///
+/// Like in ripgrep, a pattern without `/` matches file names at any depth. Other patterns match
+/// the path relative to the searched directory the same way as in `find_files`.
+pub fn build_globs(patterns: &[String]) -> Result<Option<GlobSet>, globset::Error> {
+    if patterns.is_empty() {
+        return Ok(None);
+    }
+    let patterns = patterns.iter().map(|pattern| {
+        if pattern.contains('/') { pattern.clone() } else { format!("**/{}", pattern) }
+    });
+    walk::glob_set(patterns).map(Some)
+}
+
+fn shorten(line: &str) -> String {
+    match line.char_indices().nth(MAX_LINE_CHARS) {
+        Some((cut, _)) => format!("{} [...]", &line[..cut]),
+        None => line.to_string(),
+    }
+}
+
+/// Searches the files below `root`, or `root` itself if it is a file
+///
+/// Paths in the results are relative to `base` when they are inside it. Binary files and
+/// files excluded by ignore files are skipped.
+pub fn search(root: &Path, base: &Path, options: &SearchOptions) -> SearchResults {
+    let mut results = SearchResults {
+        matches: Vec::new(),
+        rendered: String::new(),
+        files_searched: 0,
+        truncated: false,
+    };
+
+    for entry in FilteredWalk::new(root) {
+        if !entry.file_type().is_file() {
+            continue;
+        }
+        // Filters apply to the files found below a directory, not to a file searched directly
+        let relative = entry.path().strip_prefix(root).unwrap_or(entry.path());
+        if entry.depth() > 0 && options.include.as_ref().is_some_and(|globs| !globs.is_match(relative))
+            || options.exclude.as_ref().is_some_and(|globs| globs.is_match(relative))
+        {
+            continue;
+        }
+        let Ok(bytes) = fs::read(entry.path()) else {
+            continue;
+        };
+        let Decoded::Text { content, .. } = read::decode(&bytes) else {
+            continue;
+        };
+        results.files_searched += 1;
+
+        let display = entry.path().strip_prefix(base).unwrap_or(entry.path()).display().to_string();
+        let lines: Vec<&str> = content.lines().collect();
+        let first = results.matches.len();
+        for (index, line) in lines.iter().enumerate() {
+            if let Some(found) = options.regex.find(line) {
+                if results.matches.len() >= options.max_results {
+                    results.truncated = true;
+                    break;
+                }
+                results.matches.push(SearchMatch {
+                    path: display.clone(),
+                    line: index + 1,
+                    column: line[..found.start()].chars().count() + 1,
+                    text: line.to_string(),
+                });
+            }
+        }
+        render_file(&mut results.rendered, &display, &lines, &results.matches[first..], options.context_lines);
+        if results.truncated {
+            break;
+        }
+    }
+    results
+}
+
+/// Appends a file's matches as `path:line:column: text`, with context lines as `path-line- text`
+/// and `--` between groups of lines that aren't adjacent
+fn render_file(out: &mut String, path: &str, lines: &[&str], matches: &[SearchMatch], context: usize) {
+    // Index one past the last line printed for this file
+    let mut printed_until: Option<usize> = None;
+    for (i, found) in matches.iter().enumerate() {
+        let hit = found.line - 1;
+        let start = hit.saturating_sub(context).max(printed_until.unwrap_or(0));
+        if context > 0 && !out.is_empty() && printed_until.is_none_or(|printed| start > printed) {
+            out.push_str("--\n");
+        }
+        for (index, line) in lines.iter().enumerate().take(hit).skip(start) {
+            let _ = writeln!(out, "{}-{}- {}", path, index + 1, shorten(line));
+        }
+        let _ = writeln!(out, "{}:{}:{}: {}", path, found.line, found.column, shorten(&found.text));
+
+        // Trailing context stops where the next match begins
+        let mut end = (hit + 1 + context).min(lines.len());
+        if let Some(next) = matches.get(i + 1) {
+            end = end.min(next.line - 1);
+        }
+        for (index, line) in lines.iter().enumerate().take(end).skip(hit + 1) {
+            let _ = writeln!(out, "{}-{}- {}", path, index + 1, shorten(line));
+        }
+        printed_until = Some(end.max(hit + 1));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matches(pattern: &str, path: &str) -> bool {
+        build_globs(&[pattern.to_string()]).unwrap().unwrap().is_match(path)
+    }
+
+    #[test]
+    fn globs_without_a_slash_match_file_names_at_any_depth() {
+        assert!(matches("*.rs", "lib.rs"));
+        assert!(matches("*.rs", "src/mcp/search.rs"));
+        assert!(!matches("*.rs", "Cargo.toml"));
+    }
+
+    #[test]
+    fn star_does_not_match_slashes_in_paths() {
+        assert!(matches("src/*.rs", "src/lib.rs"));
+        assert!(!matches("src/*.rs", "src/mcp/search.rs"));
+        assert!(matches("src/**/*.rs", "src/mcp/search.rs"));
+    }
+}
@@ -0,0 +1,241 @@
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::process::{Child, ChildStderr, ChildStdin, ChildStdout, Command};
+
+use crate::mcp::process::{CappedBuffer, kill_process_group};
+
+/// Name of the session used when a tool call doesn't name one
+pub const DEFAULT_SESSION: &str = "default";
+
+/// Time allowed for the login scripts to run when a session starts
+const STARTUP_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Installed when a session starts so that `exit` still reports through the end marker
+const EXIT_TRAP: &str = r#"trap 'printf "\n%s exited %s %s\n" "$__corrode_marker" "$?" "$PWD"; printf "\n%s\n" "$__corrode_marker" >&2' EXIT"#;
+
+/// Output of a command run in a shell session
+pub struct ShellOutput {
+    pub stdout: String,
+    pub stderr: String,
+    /// Exit code, `None` if the command timed out or the shell exited
+    pub exit_code: Option<i32>,
+    /// Working directory of the shell after the command
+    pub cwd: Option<PathBuf>,
+    pub timed_out: bool,
+    /// The shell itself exited, e.g. because the command called `exit`
+    pub session_ended: bool,
+    /// Number of bytes dropped from the middle of stdout and stderr
+    pub stdout_dropped: usize,
+    pub stderr_dropped: usize,
+}
+
+impl ShellOutput {
+    pub fn success(&self) -> bool {
+        self.exit_code == Some(0)
+    }
+}
+
+/// A long-lived bash process that keeps its environment, working directory, functions and
+/// aliases between commands
+///
+/// Commands are written to the shell's stdin followed by a marker line that reports the exit
+/// status and working directory, which is how the end of each command's output is detected.
+pub struct ShellSession {
+    /// Working directory after the last command
+    cwd: PathBuf,
+    child: Child,
+    stdin: ChildStdin,
+    stdout: BufReader<ChildStdout>,
+    stderr: BufReader<ChildStderr>,
+}
+
+/// Reads lines into `buffer` until the marker line, returning what followed the marker on its
+/// line, or `None` if the stream ended first
+async fn read_until_marker(reader: &mut (impl AsyncBufRead + Unpin), marker: &str, buffer: &mut CappedBuffer) -> Option<String> {
+    let mut line = Vec::new();
+    loop {
+        line.clear();
+        match reader.read_until(b'\n', &mut line).await {
+            Ok(0) | Err(_) => return None,
+            Ok(_) => {}
+        }
+        if let Some(rest) = line.strip_prefix(marker.as_bytes()) {
+            return Some(String::from_utf8_lossy(rest).trim().to_string());
+        }
+        buffer.push(&line);
+    }
+}
+
+/// Returns the captured text and number of dropped bytes
+///
+/// The marker is printed after a newline so it always starts its own line; that newline is
+/// removed when the marker was seen.
+fn finish_stream(buffer: &mut CappedBuffer, saw_marker: bool) -> (String, usize) {
+    let (mut text, dropped) = buffer.finish();
+    if saw_marker && text.ends_with('\n') {
+        text.pop();
+    }
+    (text, dropped)
+}
+
+fn next_marker() -> String {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    let n = COUNTER.fetch_add(1, Ordering::Relaxed);
+    format!("__CORRODE_DONE_{}_{}__", std::process::id(), n)
+}
+
+/// Quotes a string for use as a single shell word
+pub fn quote(word: &str) -> String {
+    format!("'{}'", word.replace('\'', r"'\''"))
+}
+
+/// Checks a command line for syntax errors without running it
+///
+/// An unterminated quote or heredoc would otherwise swallow the end marker and leave the
+/// session waiting until the timeout.
+pub async fn check_syntax(command: &str) -> Result<(), String> {
+    let output = Command::new("bash")
+        .args(["-n", "-c", command])
+        .stdin(Stdio::null())
+        .output()
+        .await;
+    match output {
+        Ok(output) if !output.status.success() => Err(String::from_utf8_lossy(&output.stderr).trim().to_string()),
+        _ => Ok(()),
+    }
+}
+
+impl ShellSession {
+    /// Starts a login shell in `cwd` and waits for its startup scripts to finish
+    pub async fn start(cwd: &Path) -> io::Result<Self> {
+        let mut command = Command::new("bash");
+        command
+            .arg("-l") // Run as a login shell to load full environment
+            .arg("-s")
+            .current_dir(cwd)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true);
+        #[cfg(unix)]
+        command.process_group(0);
+
+        let mut child = command.spawn()?;
+        let (Some(stdin), Some(stdout), Some(stderr)) = (child.stdin.take(), child.stdout.take(), child.stderr.take()) else {
+            return Err(io::Error::other("failed to open the shell's standard streams"));
+        };
+
+        let mut session = Self {
+            cwd: cwd.to_path_buf(),
+            child,
+            stdin,
+            stdout: BufReader::new(stdout),
+            stderr: BufReader::new(stderr),
+        };
+
+        // Report the status and working directory when a command exits the shell, and swallow
+        // anything the login scripts print so it doesn't show up in the first command
+        let startup = session.run(EXIT_TRAP, STARTUP_TIMEOUT, 0).await?;
+        if startup.timed_out || startup.session_ended {
+            return Err(io::Error::other("the shell did not finish starting up"));
+        }
+
+        Ok(session)
+    }
+
+    /// Working directory of the shell after the last command
+    pub fn cwd(&self) -> &Path {
+        &self.cwd
+    }
+
+    /// Whether the shell process is still running
+    pub fn is_alive(&mut self) -> bool {
+        matches!(self.child.try_wait(), Ok(None))
+    }
+
+    /// Runs a command line in the session
+    ///
+    /// The command's stdin is `/dev/null` so it cannot consume the following marker lines. When
+    /// the command exceeds `timeout`, the session can no longer be used and should be dropped.
+    pub async fn run(&mut self, command: &str, timeout: Duration, max_output_bytes: usize) -> io::Result<ShellOutput> {
+        let marker = next_marker();
+        let script = format!(
+            "__corrode_marker='{marker}'\n{{\n{command}\n}} < /dev/null\nprintf '\\n{marker} %s %s\\n' \"$?\" \"$PWD\"\nprintf '\\n{marker}\\n' >&2\n"
+        );
+        self.stdin.write_all(script.as_bytes()).await?;
+        self.stdin.flush().await?;
+
+        let mut out_buffer = CappedBuffer::new(max_output_bytes);
+        let mut err_buffer = CappedBuffer::new(max_output_bytes);
+        let Self { stdout, stderr, .. } = self;
+        let reads = async {
+            tokio::join!(
+                read_until_marker(stdout, &marker, &mut out_buffer),
+                read_until_marker(stderr, &marker, &mut err_buffer)
+            )
+        };
+        let reads = tokio::time::timeout(timeout, reads).await;
+
+        let Ok((out_marker, err_marker)) = reads else {
+            self.kill();
+            let (stdout, stdout_dropped) = finish_stream(&mut out_buffer, false);
+            let (stderr, stderr_dropped) = finish_stream(&mut err_buffer, false);
+            return Ok(ShellOutput {
+                stdout,
+                stderr,
+                exit_code: None,
+                cwd: None,
+                timed_out: true,
+                session_ended: true,
+                stdout_dropped,
+                stderr_dropped,
+            });
+        };
+
+        // "<status> <cwd>" or "exited <status> <cwd>" from the EXIT trap; the cwd may contain spaces
+        let (exited, status_line) = match out_marker.as_deref() {
+            Some(rest) => match rest.strip_prefix("exited ") {
+                Some(rest) => (true, rest.split_once(' ')),
+                None => (false, rest.split_once(' ')),
+            },
+            None => (true, None),
+        };
+        let exit_code = status_line.and_then(|(status, _)| status.parse().ok());
+        let cwd = status_line.map(|(_, cwd)| PathBuf::from(cwd));
+        if let Some(cwd) = &cwd {
+            self.cwd = cwd.clone();
+        }
+        let (stdout, stdout_dropped) = finish_stream(&mut out_buffer, out_marker.is_some());
+        let (stderr, stderr_dropped) = finish_stream(&mut err_buffer, err_marker.is_some());
+
+        Ok(ShellOutput {
+            stdout,
+            stderr,
+            exit_code,
+            cwd,
+            timed_out: false,
+            session_ended: exited,
+            stdout_dropped,
+            stderr_dropped,
+        })
+    }
+
+    /// Kills the shell and everything it started in its process group
+    pub fn kill(&mut self) {
+        if let Some(pid) = self.child.id() {
+            kill_process_group(pid);
+        }
+        let _ = self.child.start_kill();
+    }
+}
+
+impl Drop for ShellSession {
+    fn drop(&mut self) {
+        self.kill();
+    }
+}
@@ -0,0 +1,378 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::path::Path;
+
+use crate::mcp::cargo::CargoOptions;
+use crate::mcp::diagnostics::Diagnostic;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum TestStatus {
+    Passed,
+    Failed,
+    Ignored,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct TestCase {
+    pub name: String,
+    /// Package whose test binary ran the test, when cargo's output tells
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub package: Option<String>,
+    pub status: TestStatus,
+    /// Execution time in seconds, only reported by libtest's JSON format
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub duration_secs: Option<f64>,
+    /// Output captured while the test ran (reported for failures)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stdout: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub panic_message: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Default, Debug)]
+pub struct TestReport {
+    pub success: bool,
+    pub passed: usize,
+    pub failed: usize,
+    pub ignored: usize,
+    pub tests: Vec<TestCase>,
+    /// Compiler errors when the tests failed to build
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub build_errors: Vec<Diagnostic>,
+}
+
+/// A failed test, remembered so `rerun_failed` can run it again in its package
+#[derive(Clone, Debug)]
+pub struct FailedTest {
+    pub package: Option<String>,
+    pub name: String,
+}
+
+/// Builds the `cargo test` arguments
+///
+/// `filters` are passed to libtest; with `exact` only tests with exactly these names run. With
+/// `json` libtest reports per-test events and timings, which requires a nightly toolchain.
+pub fn build_args(options: &CargoOptions, filters: &[String], exact: bool, json: bool) -> Vec<String> {
+    let mut args = vec!["test".to_string(), "--message-format=json".to_string()];
+
+    if let Some(package) = &options.package {
+        args.push("-p".into());
+        args.push(package.clone());
+    }
+    if options.workspace.unwrap_or(false) {
+        args.push("--workspace".into());
+    }
+    if let Some(features) = &options.features {
+        args.push("--features".into());
+        args.push(features.clone());
+    }
+    if options.all_features.unwrap_or(false) {
+        args.push("--all-features".into());
+    }
+    if options.no_default_features.unwrap_or(false) {
+        args.push("--no-default-features".into());
+    }
+    if let Some(target) = &options.target {
+        args.push("--target".into());
+        args.push(target.clone());
+    }
+    if let Some(profile) = &options.profile {
+        args.push("--profile".into());
+        args.push(profile.clone());
+    }
+
+    // Keep going after a failing test binary so every failure is reported
+    args.push("--no-fail-fast".into());
+
+    args.push("--".into());
+    args.extend(filters.iter().cloned());
+    if exact {
+        args.push("--exact".into());
+    }
+    if json {
+        args.extend(["-Z", "unstable-options", "--format", "json", "--report-time"].map(String::from));
+    }
+
+    args
+}
+
+/// Splits a failure's captured output into what the test printed and the panic message
+fn split_panic(output: &str) -> (Option<String>, Option<String>) {
+    let Some(start) = output.find("\nthread '").map(|i| i + 1).or_else(|| output.starts_with("thread '").then_some(0)) else {
+        let stdout = output.trim_end();
+        return ((!stdout.is_empty()).then(|| stdout.to_string()), None);
+    };
+
+    let stdout = output[..start].trim_end();
+    let panic = output[start..]
+        .lines()
+        .take_while(|l| !l.starts_with("stack backtrace:") && !l.starts_with("note: run with `RUST_BACKTRACE"))
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    ((!stdout.is_empty()).then(|| stdout.to_string()), Some(panic.trim_end().to_string()))
+}
+
+#[derive(Deserialize)]
+struct CargoArtifact {
+    reason: String,
+    package_id: Option<String>,
+    target: Option<ArtifactTarget>,
+    executable: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct ArtifactTarget {
+    name: String,
+    kind: Vec<String>,
+}
+
+/// Package name from a package ID, e.g. `foo` from `path+file:///src/foo#0.1.0`,
+/// `path+file:///src/bar#foo@0.1.0` or the older `foo 0.1.0 (path+file:///src/foo)`
+fn package_name(id: &str) -> String {
+    match id.rsplit_once('#') {
+        Some((_, spec)) if spec.contains('@') => spec.split('@').next().unwrap_or(spec).to_string(),
+        // The name is left out when it equals the last component of the source path
+        Some((source, _)) => source.trim_end_matches('/').rsplit('/').next().unwrap_or(source).to_string(),
+        None => id.split(' ').next().unwrap_or(id).to_string(),
+    }
+}
+
+fn file_name(path: &str) -> &str {
+    Path::new(path).file_name().and_then(|name| name.to_str()).unwrap_or(path)
+}
+
+/// Packages of the test binaries in the order cargo ran them, `None` where it is unknown
+///
+/// Cargo names each binary on stderr, e.g. `Running unittests src/lib.rs
+/// (target/debug/deps/foo-1a2b3c)` or `Doc-tests foo`, and its `--message-format=json` artifacts
+/// on stdout tell which package built it.
+pub fn suite_packages(stdout: &str, stderr: &str) -> Vec<Option<String>> {
+    let mut executables = HashMap::new();
+    let mut libraries = HashMap::new();
+    for line in stdout.lines().filter(|line| line.starts_with('{')) {
+        let Ok(artifact) = serde_json::from_str::<CargoArtifact>(line) else {
+            continue;
+        };
+        let (Some(id), "compiler-artifact") = (&artifact.package_id, artifact.reason.as_str()) else {
+            continue;
+        };
+        let package = package_name(id);
+        if let Some(executable) = &artifact.executable {
+            executables.insert(file_name(executable).to_string(), package.clone());
+        }
+        if let Some(target) = &artifact.target
+            && target.kind.iter().any(|kind| kind.ends_with("lib") || kind == "proc-macro")
+        {
+            libraries.insert(target.name.replace('-', "_"), package);
+        }
+    }
+
+    stderr
+        .lines()
+        .filter_map(|line| {
+            let line = line.trim();
+            if let Some(rest) = line.strip_prefix("Running ") {
+                let executable = rest.rsplit_once('(').and_then(|(_, path)| path.strip_suffix(')'));
+                Some(executable.and_then(|path| executables.get(file_name(path)).cloned()))
+            } else {
+                line.strip_prefix("Doc-tests ").map(|name| libraries.get(name.trim()).cloned())
+            }
+        })
+        .collect()
+}
+
+#[derive(Deserialize)]
+struct JsonTestEvent {
+    #[serde(rename = "type")]
+    kind: String,
+    event: String,
+    name: Option<String>,
+    exec_time: Option<f64>,
+    stdout: Option<String>,
+}
+
+/// Parses libtest output, either the human readable format or `--format json` lines
+///
+/// Lines emitted by cargo itself (`--message-format=json`) are skipped; the compiler
+/// diagnostics among them are parsed separately with `parse_cargo_messages`. `suites` are the
+/// packages of the test binaries in the order they ran, see `suite_packages`.
+pub fn parse_test_output(stdout: &str, suites: &[Option<String>]) -> Vec<TestCase> {
+    let mut tests: Vec<TestCase> = Vec::new();
+    let mut lines = stdout.lines().peekable();
+    let mut suite: Option<usize> = None;
+    let package = |suite: Option<usize>| suite.and_then(|i| suites.get(i).cloned().flatten());
+
+    while let Some(line) = lines.next() {
+        if line.starts_with('{') {
+            let Ok(event) = serde_json::from_str::<JsonTestEvent>(line) else {
+                continue;
+            };
+            if event.kind == "suite" && event.event == "started" {
+                suite = Some(suite.map_or(0, |i| i + 1));
+                continue;
+            }
+            let (Some(name), "test") = (event.name, event.kind.as_str()) else {
+                continue;
+            };
+            let status = match event.event.as_str() {
+                "ok" => TestStatus::Passed,
+                "failed" | "timeout" => TestStatus::Failed,
+                "ignored" => TestStatus::Ignored,
+                _ => continue,
+            };
+            let (captured, panic_message) = event.stdout.as_deref().map(split_panic).unwrap_or_default();
+            tests.push(TestCase {
+                name,
+                package: package(suite),
+                status,
+                duration_secs: event.exec_time,
+                stdout: captured,
+                panic_message,
+            });
+        } else if line.starts_with("running ") && (line.ends_with(" test") || line.ends_with(" tests")) {
+            // `running 3 tests` starts the output of the next test binary
+            suite = Some(suite.map_or(0, |i| i + 1));
+        } else if let Some(rest) = line.strip_prefix("test ") {
+            // `test tests::name ... ok`
+            let Some((name, outcome)) = rest.rsplit_once(" ... ") else {
+                continue;
+            };
+            let status = match outcome.trim() {
+                "ok" => TestStatus::Passed,
+                "FAILED" => TestStatus::Failed,
+                s if s.starts_with("ignored") => TestStatus::Ignored,
+                _ => continue,
+            };
+            tests.push(TestCase {
+                name: name.trim().to_string(),
+                package: package(suite),
+                status,
+                duration_secs: None,
+                stdout: None,
+                panic_message: None,
+            });
+        } else if let Some(header) = line.strip_prefix("---- ").and_then(|l| l.strip_suffix(" stdout ----")) {
+            // Captured output of a failed test, up to the next section
+            let mut section = Vec::new();
+            while let Some(next) = lines.peek() {
+                if next.starts_with("---- ") || *next == "failures:" {
+                    break;
+                }
+                section.push(lines.next().unwrap_or_default());
+            }
+            let (captured, panic_message) = split_panic(&section.join("\n"));
+
+            if let Some(test) = tests
+                .iter_mut()
+                .rev()
+                .find(|t| t.name == header && t.status == TestStatus::Failed && t.panic_message.is_none())
+            {
+                test.stdout = captured;
+                test.panic_message = panic_message;
+            }
+        }
+    }
+
+    tests
+}
+
+/// Builds a report with pass/fail/ignore counts
+pub fn build_report(tests: Vec<TestCase>, build_errors: Vec<Diagnostic>, success: bool) -> TestReport {
+    let count = |status| tests.iter().filter(|t| t.status == status).count();
+    TestReport {
+        success,
+        passed: count(TestStatus::Passed),
+        failed: count(TestStatus::Failed),
+        ignored: count(TestStatus::Ignored),
+        tests,
+        build_errors,
+    }
+}
+
+/// Renders the failures in detail, followed by the ignored and passed test names
+pub fn render_test_report(report: &TestReport) -> String {
+    let mut out = format!(
+        "{}: {} passed, {} failed, {} ignored\n",
+        if report.success { "Succeeded" } else { "Failed" },
+        report.passed,
+        report.failed,
+        report.ignored
+    );
+
+    if !report.build_errors.is_empty() {
+        out.push_str("\nThe tests failed to compile:\n");
+        for error in &report.build_errors {
+            match &error.location {
+                Some(l) => {
+                    let _ = writeln!(out, "  {}: {} ({}:{}:{})", error.level, error.message, l.file, l.line, l.column);
+                }
+                None => {
+                    let _ = writeln!(out, "  {}: {}", error.level, error.message);
+                }
+            }
+        }
+    }
+
+    for test in report.tests.iter().filter(|t| t.status == TestStatus::Failed) {
+        let _ = write!(out, "\nFAILED {}", test.name);
+        if let Some(duration) = test.duration_secs {
+            let _ = write!(out, " ({:.3}s)", duration);
+        }
+        out.push('\n');
+        if let Some(panic) = &test.panic_message {
+            let _ = writeln!(out, "{}", panic);
+        }
+        if let Some(stdout) = &test.stdout {
+            let _ = writeln!(out, "captured stdout:\n{}", stdout);
+        }
+    }
+
+    for (label, status) in [("Ignored", TestStatus::Ignored), ("Passed", TestStatus::Passed)] {
+        let names: Vec<&str> = report
+            .tests
+            .iter()
+            .filter(|t| t.status == status)
+            .map(|t| t.name.as_str())
+            .collect();
+        if !names.is_empty() {
+            let _ = writeln!(out, "\n{}:\n  {}", label, names.join("\n  "));
+        }
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_package_names_from_package_ids() {
+        assert_eq!(package_name("path+file:///src/alpha#0.1.0"), "alpha");
+        assert_eq!(package_name("path+file:///src/beta#beta-pkg@0.1.0"), "beta-pkg");
+        assert_eq!(package_name("alpha 0.1.0 (path+file:///src/alpha)"), "alpha");
+    }
+
+    #[test]
+    fn assigns_tests_to_the_package_of_their_binary() {
+        let stdout = [
+            r#"{"reason":"compiler-artifact","package_id":"path+file:///ws/alpha#0.1.0","target":{"kind":["lib"],"name":"alpha"},"executable":"/ws/target/debug/deps/alpha-1a2b"}"#,
+            r#"{"reason":"compiler-artifact","package_id":"path+file:///ws/beta#beta-pkg@0.1.0","target":{"kind":["lib"],"name":"beta-pkg"},"executable":"/ws/target/debug/deps/beta_pkg-3c4d"}"#,
+            "running 1 test",
+            "test tests::shared ... FAILED",
+            "running 1 test",
+            "test tests::shared ... ok",
+            "running 1 test",
+            "test src/lib.rs - f (line 1) ... ok",
+        ]
+        .join("\n");
+        let stderr = "     Running unittests src/lib.rs (target/debug/deps/alpha-1a2b)\n     Running unittests src/lib.rs (target/debug/deps/beta_pkg-3c4d)\n   Doc-tests beta_pkg\n";
+
+        let tests = parse_test_output(&stdout, &suite_packages(&stdout, stderr));
+        let packages: Vec<Option<&str>> = tests.iter().map(|t| t.package.as_deref()).collect();
+        assert_eq!(packages, [Some("alpha"), Some("beta-pkg"), Some("beta-pkg")]);
+    }
+}
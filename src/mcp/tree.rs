@@ -0,0 +1,92 @@
+use std::fmt::Write as _;
+use std::path::Path;
+
+use crate::mcp::walk::FilteredWalk;
+
+/// Depth shown when `list_directory` isn't given one
+pub const DEFAULT_TREE_DEPTH: usize = 2;
+
+/// Entries shown before the listing is cut short
+const MAX_TREE_ENTRIES: usize = 1000;
+
+struct Node {
+    name: String,
+    depth: usize,
+    is_dir: bool,
+    /// Size of the file, or total size of the files below the directory
+    size: u64,
+    /// Files below the directory, at any depth
+    files: usize,
+}
+
+/// Formats a byte count for humans, e.g. `912 B`, `14.2 KB`, `3.1 MB`
+pub fn format_size(bytes: u64) -> String {
+    const UNITS: &[&str] = &["KB", "MB", "GB", "TB"];
+    if bytes < 1024 {
+        return format!("{} B", bytes);
+    }
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while size >= 1024.0 && unit + 1 < UNITS.len() {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", size, UNITS[unit])
+}
+
+/// Renders the directory tree below `root` down to `max_depth` levels
+///
+/// Every directory shows the number and total size of the files below it, including those
+/// deeper than `max_depth`. Ignored files and build directories are left out.
+pub fn render_tree(root: &Path, max_depth: usize) -> String {
+    let mut nodes: Vec<Node> = Vec::new();
+    // Indices of the directories enclosing the current entry, outermost first
+    let mut open_dirs: Vec<usize> = Vec::new();
+
+    for entry in FilteredWalk::new(root) {
+        let depth = entry.depth();
+        while open_dirs.last().is_some_and(|&dir| nodes[dir].depth >= depth) {
+            open_dirs.pop();
+        }
+        let is_dir = entry.file_type().is_dir();
+        let size = if is_dir { 0 } else { entry.metadata().map(|m| m.len()).unwrap_or(0) };
+
+        if !is_dir {
+            for &dir in &open_dirs {
+                nodes[dir].size += size;
+                nodes[dir].files += 1;
+            }
+        }
+        // Entries below the depth limit only count towards the directories that are shown
+        if depth <= max_depth {
+            let name = if depth == 0 {
+                root.display().to_string()
+            } else {
+                entry.file_name().to_string_lossy().to_string()
+            };
+            nodes.push(Node { name, depth, is_dir, size, files: 0 });
+            if is_dir {
+                open_dirs.push(nodes.len() - 1);
+            }
+        }
+    }
+
+    let mut out = String::new();
+    for node in nodes.iter().take(MAX_TREE_ENTRIES) {
+        let indent = "  ".repeat(node.depth);
+        if node.is_dir {
+            let files = if node.files == 1 { "1 file".to_string() } else { format!("{} files", node.files) };
+            let _ = writeln!(out, "{}{}/ ({}, {})", indent, node.name, files, format_size(node.size));
+        } else {
+            let _ = writeln!(out, "{}{} ({})", indent, node.name, format_size(node.size));
+        }
+    }
+    if nodes.len() > MAX_TREE_ENTRIES {
+        let _ = writeln!(
+            out,
+            "\n[... {} more entries not shown. List a subdirectory or use a smaller depth ...]",
+            nodes.len() - MAX_TREE_ENTRIES
+        );
+    }
+    out
+}
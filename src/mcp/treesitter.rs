@@ -1,10 +1,13 @@
 #![allow(dead_code)]
+#![allow(clippy::missing_safety_doc)]
 use serde::{Deserialize, Serialize};
 
-use std::collections::HashMap;
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
 use std::fs;
-use std::path::Path;
-use tree_sitter::{Language, Node, Parser, Query, QueryCursor};
+use std::path::{Path, PathBuf};
+use walkdir::{DirEntry, WalkDir};
+use tree_sitter::{Language, Node, Parser, Query, QueryCursor, Tree};
 
 extern crate tree_sitter_rust as rust;
 extern crate tree_sitter_javascript as javascript;
@@ -16,7 +19,7 @@ extern crate tree_sitter_cpp as cpp;
 
 #[derive(Serialize, Deserialize)]
 pub struct ProjectStructure {
-    pub files: HashMap<String, FileInfo>,
+    pub files: BTreeMap<String, FileInfo>,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -30,9 +33,13 @@ pub struct FileInfo {
     #[serde(skip_serializing_if = "Vec::is_empty")]
     pub structs: Vec<StructInfo>,
     #[serde(skip_serializing_if = "Vec::is_empty")]
-    pub enums: Vec<String>,
+    pub enums: Vec<ItemInfo>,
     #[serde(skip_serializing_if = "Vec::is_empty")]
-    pub imports: Vec<String>,
+    pub traits: Vec<ItemInfo>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub impls: Vec<ItemInfo>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub imports: Vec<ItemInfo>,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -64,6 +71,14 @@ pub struct StructInfo {
     pub methods: Vec<FunctionInfo>,
 }
 
+/// A named item such as an enum, trait, impl block or import
+#[derive(Serialize, Deserialize)]
+pub struct ItemInfo {
+    pub name: String,
+    pub start_line: usize,
+    pub end_line: Option<usize>,
+}
+
 // Map file extension to language
 pub fn detect_language(file_path: &Path, language_override: Option<&str>) -> Option<(Language, String)> {
     if let Some(lang) = language_override {
@@ -98,24 +113,40 @@ pub fn detect_language(file_path: &Path, language_override: Option<&str>) -> Opt
 }
 
 fn get_query_for_language(language: Language) -> Option<String> {
+    // Captures are placed on whole definitions so line ranges cover the full item
     if language == unsafe { tree_sitter_rust() } {
         Some(
             r#"
-            (function_item name: (identifier) @function) 
-            (impl_item name: (type_identifier) @impl) 
-            (struct_item name: (type_identifier) @struct) 
-            (enum_item name: (type_identifier) @enum) 
-            (trait_item name: (type_identifier) @trait) 
-            (function_item name: (identifier) @method) 
+            (function_item) @function
+            (function_signature_item) @function
+            (impl_item) @impl
+            (struct_item) @struct
+            (enum_item) @enum
+            (trait_item) @trait
             (use_declaration) @import"#
                 .to_string(),
         )
-    } else if language == unsafe { tree_sitter_javascript() } || language == unsafe { tree_sitter_typescript() } {
+    } else if language == unsafe { tree_sitter_javascript() } {
         Some(
             r#"
-            (function_declaration name: (identifier) @function)
-            (method_definition name: (property_identifier) @method)
-            (class_declaration name: (identifier) @class)
+            (function_declaration) @function
+            (generator_function_declaration) @function
+            (method_definition) @function
+            (class_declaration) @class
+            (import_statement) @import
+        "#
+            .to_string(),
+        )
+    } else if language == unsafe { tree_sitter_typescript() } {
+        Some(
+            r#"
+            (function_declaration) @function
+            (generator_function_declaration) @function
+            (method_definition) @function
+            (class_declaration) @class
+            (abstract_class_declaration) @class
+            (interface_declaration) @trait
+            (enum_declaration) @enum
             (import_statement) @import
         "#
             .to_string(),
@@ -123,30 +154,42 @@ fn get_query_for_language(language: Language) -> Option<String> {
     } else if language == unsafe { tree_sitter_python() } {
         Some(
             r#"
-            (function_definition name: (identifier) @function)
-            (class_definition name: (identifier) @class)
+            (function_definition) @function
+            (class_definition) @class
             (import_statement) @import
+            (import_from_statement) @import
         "#
             .to_string(),
         )
     } else if language == unsafe { tree_sitter_go() } {
         Some(
             r#"
-            (function_declaration name: (identifier) @function)
-            (method_declaration name: (field_identifier) @method receiver: (parameter_list) @receiver)
-            (type_declaration (type_spec name: (type_identifier) @type))
+            (function_declaration) @function
+            (method_declaration) @function
+            (type_spec type: (struct_type)) @struct
+            (type_spec type: (interface_type)) @trait
             (import_declaration) @import
         "#
             .to_string(),
         )
-    } else if language == unsafe { tree_sitter_c() } || language == unsafe { tree_sitter_cpp() } {
+    } else if language == unsafe { tree_sitter_c() } {
+        Some(
+            r#"
+            (function_definition) @function
+            (struct_specifier name: (type_identifier) body: (field_declaration_list)) @struct
+            (enum_specifier name: (type_identifier) body: (enumerator_list)) @enum
+            (preproc_include) @import
+        "#
+            .to_string(),
+        )
+    } else if language == unsafe { tree_sitter_cpp() } {
         Some(
             r#"
-            (function_definition declarator: (function_declarator declarator: (identifier) @function))
-            (struct_specifier name: (type_identifier) @struct)
-            (class_specifier name: (type_identifier) @class)
-            (enum_specifier name: (type_identifier) @enum)
-            (include_directive) @import
+            (function_definition) @function
+            (struct_specifier name: (type_identifier) body: (field_declaration_list)) @struct
+            (class_specifier name: (type_identifier) body: (field_declaration_list)) @class
+            (enum_specifier name: (type_identifier) body: (enumerator_list)) @enum
+            (preproc_include) @import
         "#
             .to_string(),
         )
@@ -169,92 +212,322 @@ fn get_end_line(node: &Node, _source: &str) -> Option<usize> {
     Some(pos.row + 1)
 }
 
-// Parse a single file and return its structure
-pub fn parse_file(file_path: &Path, language_override: Option<&str>) -> Option<FileInfo> {
+fn node_text<'a>(node: Node, source: &'a str) -> &'a str {
+    node.utf8_text(source.as_bytes()).unwrap_or("unknown")
+}
+
+/// Returns the name of a definition node (function, struct, class, impl, ...)
+pub fn definition_name(node: Node, source: &str) -> String {
+    match node.kind() {
+        // `impl<T> Display for Foo<T>`: everything up to the body reads best
+        "impl_item" => {
+            let end = node
+                .child_by_field_name("body")
+                .map_or(node.end_byte(), |body| body.start_byte());
+            return source[node.start_byte()..end].split_whitespace().collect::<Vec<_>>().join(" ");
+        }
+        // Imports are identified by their full text
+        "use_declaration" | "import_statement" | "import_from_statement" | "import_declaration" | "preproc_include" => {
+            return node_text(node, source).split_whitespace().collect::<Vec<_>>().join(" ");
+        }
+        _ => {}
+    }
+
+    if let Some(name) = node.child_by_field_name("name") {
+        return node_text(name, source).to_string();
+    }
+
+    // C and C++ nest the name inside (possibly pointer/reference) declarators
+    let mut declarator = node.child_by_field_name("declarator");
+    while let Some(current) = declarator {
+        match current.child_by_field_name("declarator") {
+            Some(inner) => declarator = Some(inner),
+            None => return node_text(current, source).to_string(),
+        }
+    }
+
+    "unknown".to_string()
+}
+
+/// Returns the name of the impl, trait, class or receiver type a function belongs to
+pub fn parent_name(node: Node, source: &str) -> Option<String> {
+    // Go methods declare their parent through the receiver, e.g. `(s *Server)` gives `Server`
+    if node.kind() == "method_declaration" {
+        let receiver = node.child_by_field_name("receiver")?;
+        let mut cursor = receiver.walk();
+        let param = receiver.named_children(&mut cursor).next()?;
+        let ty = node_text(param.child_by_field_name("type")?, source);
+        return Some(ty.trim_start_matches('*').to_string());
+    }
+
+    let mut current = node.parent();
+    while let Some(ancestor) = current {
+        let name_node = match ancestor.kind() {
+            // `impl<'a> Candidate<'a>` belongs to `Candidate`
+            "impl_item" => ancestor.child_by_field_name("type").map(|ty| {
+                if ty.kind() == "generic_type" {
+                    ty.child_by_field_name("type").unwrap_or(ty)
+                } else {
+                    ty
+                }
+            }),
+            "trait_item" | "class_declaration" | "class" | "abstract_class_declaration" | "interface_declaration"
+            | "class_definition" | "class_specifier" | "struct_specifier" | "type_spec" => {
+                ancestor.child_by_field_name("name")
+            }
+            _ => None,
+        };
+        if let Some(name_node) = name_node {
+            return Some(node_text(name_node, source).to_string());
+        }
+        current = ancestor.parent();
+    }
+    None
+}
+
+// Collect the field names declared in a struct body
+fn struct_fields(node: Node, source: &str) -> Vec<String> {
+    let body = node.child_by_field_name("body").or_else(|| {
+        // Go keeps the fields in the struct_type under the type_spec
+        let struct_type = node.child_by_field_name("type")?;
+        let mut cursor = struct_type.walk();
+        struct_type
+            .named_children(&mut cursor)
+            .find(|c| c.kind() == "field_declaration_list")
+    });
+    let Some(body) = body else {
+        return Vec::new();
+    };
+
+    let mut cursor = body.walk();
+    body.named_children(&mut cursor)
+        .filter(|c| c.kind() == "field_declaration")
+        .map(|field| {
+            let name = field
+                .child_by_field_name("name")
+                .or_else(|| field.child_by_field_name("declarator"));
+            name.map_or_else(|| definition_name(field, source), |n| node_text(n, source).to_string())
+        })
+        .collect()
+}
+
+/// A syntax tree together with the source it was parsed from
+pub struct ParsedSource {
+    pub tree: Tree,
+    pub source: String,
+    pub language: Language,
+    pub language_name: String,
+}
+
+// Read and parse a single file into a syntax tree
+pub fn parse_tree(file_path: &Path, language_override: Option<&str>) -> Option<ParsedSource> {
+    // Detect language first so unsupported files are skipped without being read
+    let (language, language_name) = detect_language(file_path, language_override)?;
+
     // Read the file content
     let source = fs::read_to_string(file_path).ok()?;
-    
-    // Detect language and set up TreeSitter
-    let (lang, lang_name) = detect_language(file_path, language_override)?;
-    
+
     let mut parser = Parser::new();
-    if parser.set_language(lang).is_err() {
+    if parser.set_language(language).is_err() {
         return None;
     }
-    
+
     // Parse the source code
     let tree = parser.parse(&source, None)?;
+
+    Some(ParsedSource {
+        tree,
+        source,
+        language,
+        language_name,
+    })
+}
+
+// Parse a single file and return its structure
+pub fn parse_file(file_path: &Path, language_override: Option<&str>) -> Option<FileInfo> {
+    let ParsedSource { tree, source, language: lang, language_name: lang_name } =
+        parse_tree(file_path, language_override)?;
     let root_node = tree.root_node();
-    
+
     // Create the query for extracting code structure
     let query_string = get_query_for_language(lang)?;
-    
+
     // Try to create the query, handling potential errors
     let query = Query::new(lang, &query_string).ok()?;
     let mut cursor = QueryCursor::new();
     let matches = cursor.matches(&query, root_node, source.as_bytes());
-    
+
     let mut functions: Vec<FunctionInfo> = Vec::new();
     let mut classes: Vec<ClassInfo> = Vec::new();
     let mut structs: Vec<StructInfo> = Vec::new();
-    let mut enums: Vec<String> = Vec::new();
-    let mut imports: Vec<String> = Vec::new();
-    
+    let mut enums: Vec<ItemInfo> = Vec::new();
+    let mut traits: Vec<ItemInfo> = Vec::new();
+    let mut impls: Vec<ItemInfo> = Vec::new();
+    let mut imports: Vec<ItemInfo> = Vec::new();
+
     for m in matches {
         for capture in m.captures {
-            let capture_name = query.capture_names()[capture.index as usize].to_string();
-            let name = capture.node.utf8_text(source.as_bytes()).unwrap_or("unknown").to_string();
-            
-            match capture_name.as_str() {
+            let capture_name = query.capture_names()[capture.index as usize].as_str();
+            let node = capture.node;
+            let name = definition_name(node, &source);
+            let start_line = get_line(&node, &source);
+            let end_line = get_end_line(&node, &source);
+
+            match capture_name {
                 "function" => {
                     functions.push(FunctionInfo {
                         name,
-                        start_line: get_line(&capture.node, &source),
-                        end_line: get_end_line(&capture.node, &source),
-                        parent: None,
+                        start_line,
+                        end_line,
+                        parent: parent_name(node, &source),
                     });
                 },
                 "class" => {
                     classes.push(ClassInfo {
                         name,
-                        start_line: get_line(&capture.node, &source),
-                        end_line: get_end_line(&capture.node, &source),
+                        start_line,
+                        end_line,
                         methods: Vec::new(),
                     });
                 },
                 "struct" => {
                     structs.push(StructInfo {
                         name,
-                        start_line: get_line(&capture.node, &source),
-                        end_line: get_end_line(&capture.node, &source),
-                        fields: Vec::new(),
+                        start_line,
+                        end_line,
+                        fields: struct_fields(node, &source),
                         methods: Vec::new(),
                     });
                 },
-                "enum" => {
-                    enums.push(name);
-                },
-                "import" => {
-                    imports.push(name);
-                },
+                "enum" => enums.push(ItemInfo { name, start_line, end_line }),
+                "trait" => traits.push(ItemInfo { name, start_line, end_line }),
+                "impl" => impls.push(ItemInfo { name, start_line, end_line }),
+                "import" => imports.push(ItemInfo { name, start_line, end_line }),
                 _ => {}
             }
         }
     }
-    
+
+    // Attach methods to the class or struct they belong to when it is defined in this file
+    let mut free_functions = Vec::new();
+    for function in functions {
+        let owner = function.parent.as_deref();
+        if let Some(class) = classes.iter_mut().find(|c| Some(c.name.as_str()) == owner) {
+            class.methods.push(function);
+        } else if let Some(st) = structs.iter_mut().find(|s| Some(s.name.as_str()) == owner) {
+            st.methods.push(function);
+        } else {
+            free_functions.push(function);
+        }
+    }
+
     let rel_path = file_path.to_string_lossy().to_string();
-    
+
     Some(FileInfo {
         path: rel_path,
         language: lang_name,
-        functions,
+        functions: free_functions,
         classes,
         structs,
         enums,
+        traits,
+        impls,
         imports,
     })
 }
 
+// Directories that never contain project sources worth scanning
+const SKIPPED_DIRS: &[&str] = &["target", "node_modules", "vendor", "__pycache__"];
+
+fn is_skipped_dir(entry: &DirEntry) -> bool {
+    // Never skip the root, even if it is e.g. `.` or a hidden directory
+    if entry.depth() == 0 || !entry.file_type().is_dir() {
+        return false;
+    }
+    let name = entry.file_name().to_string_lossy();
+    name.starts_with('.') || SKIPPED_DIRS.contains(&name.as_ref())
+}
+
+// List all files under a directory in a supported language, skipping build and hidden directories
+pub fn source_files(project_dir: &Path) -> Vec<PathBuf> {
+    WalkDir::new(project_dir)
+        .sort_by_file_name()
+        .into_iter()
+        .filter_entry(|e| !is_skipped_dir(e))
+        .filter_map(|e| e.ok())
+        .filter(|e| e.file_type().is_file() && detect_language(e.path(), None).is_some())
+        .map(|e| e.into_path())
+        .collect()
+}
+
+// Parse every supported file in a directory tree, keyed by path relative to the directory
+pub fn parse_project(project_dir: &Path) -> ProjectStructure {
+    let mut files = BTreeMap::new();
+
+    for path in source_files(project_dir) {
+        if let Some(mut info) = parse_file(&path, None) {
+            let rel_path = path
+                .strip_prefix(project_dir)
+                .unwrap_or(&path)
+                .to_string_lossy()
+                .to_string();
+            info.path = rel_path.clone();
+            files.insert(rel_path, info);
+        }
+    }
+
+    ProjectStructure { files }
+}
+
+fn line_range(start_line: usize, end_line: Option<usize>) -> String {
+    match end_line {
+        Some(end) if end != start_line => format!("{}-{}", start_line, end),
+        _ => start_line.to_string(),
+    }
+}
+
+// Render a file outline as an indented, human readable listing
+pub fn render_outline(file: &FileInfo) -> String {
+    let mut out = format!("{} ({})\n", file.path, file.language);
+
+    for import in &file.imports {
+        let _ = writeln!(out, "  import [{}] {}", line_range(import.start_line, import.end_line), import.name);
+    }
+    for item in &file.structs {
+        let _ = write!(out, "  struct {} [{}]", item.name, line_range(item.start_line, item.end_line));
+        if !item.fields.is_empty() {
+            let _ = write!(out, " fields: {}", item.fields.join(", "));
+        }
+        out.push('\n');
+        for method in &item.methods {
+            let _ = writeln!(out, "    fn {} [{}]", method.name, line_range(method.start_line, method.end_line));
+        }
+    }
+    for item in &file.classes {
+        let _ = writeln!(out, "  class {} [{}]", item.name, line_range(item.start_line, item.end_line));
+        for method in &item.methods {
+            let _ = writeln!(out, "    fn {} [{}]", method.name, line_range(method.start_line, method.end_line));
+        }
+    }
+    for item in &file.enums {
+        let _ = writeln!(out, "  enum {} [{}]", item.name, line_range(item.start_line, item.end_line));
+    }
+    for item in &file.traits {
+        let _ = writeln!(out, "  trait {} [{}]", item.name, line_range(item.start_line, item.end_line));
+    }
+    for item in &file.impls {
+        let _ = writeln!(out, "  {} [{}]", item.name, line_range(item.start_line, item.end_line));
+    }
+    for function in &file.functions {
+        let _ = write!(out, "  fn {} [{}]", function.name, line_range(function.start_line, function.end_line));
+        if let Some(parent) = &function.parent {
+            let _ = write!(out, " (in {})", parent);
+        }
+        out.push('\n');
+    }
+
+    out
+}
 
 // Safely get the language functions
 pub unsafe fn tree_sitter_rust() -> Language {
@@ -280,4 +553,77 @@ pub unsafe fn tree_sitter_c() -> Language {
 }
 pub unsafe fn tree_sitter_cpp() -> Language {
     cpp::language()
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write_source(name: &str, source: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("corrode-treesitter-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(name);
+        fs::write(&path, source).unwrap();
+        path
+    }
+
+    #[test]
+    fn detects_languages_by_extension_or_override() {
+        let name = |path: &str, language: Option<&str>| {
+            detect_language(Path::new(path), language).map(|(_, name)| name)
+        };
+        assert_eq!(name("src/lib.rs", None).as_deref(), Some("rust"));
+        assert_eq!(name("include/x.hpp", None).as_deref(), Some("cpp"));
+        assert_eq!(name("script", Some("python")).as_deref(), Some("python"));
+        assert!(name("README.md", None).is_none());
+        assert!(name("src/lib.rs", Some("cobol")).is_none());
+    }
+
+    #[test]
+    fn skips_unsupported_files_without_reading_them() {
+        assert!(parse_tree(Path::new("/nonexistent/notes.txt"), None).is_none());
+
+        let parsed = parse_tree(&write_source("tree.rs", "fn main() {}\n"), None).unwrap();
+        assert_eq!(parsed.language_name, "rust");
+        assert_eq!(parsed.tree.root_node().kind(), "source_file");
+    }
+
+    #[test]
+    fn outlines_rust_items_and_attaches_methods_to_their_struct() {
+        let source = "use std::fmt;\n\n\
+            pub struct Point {\n    x: i32,\n    y: i32,\n}\n\n\
+            impl<'a> Point {\n    fn norm(&self) -> i32 {\n        self.x\n    }\n}\n\n\
+            enum Shape {\n    Dot,\n}\n\n\
+            trait Area {\n    fn area(&self) -> f64;\n}\n\n\
+            fn main() {}\n";
+        let info = parse_file(&write_source("outline.rs", source), None).unwrap();
+
+        assert_eq!(info.imports[0].name, "use std::fmt;");
+        assert_eq!(info.structs[0].name, "Point");
+        assert_eq!(info.structs[0].fields, ["x", "y"]);
+        assert_eq!((info.structs[0].start_line, info.structs[0].end_line), (3, Some(6)));
+        assert_eq!(info.structs[0].methods[0].name, "norm");
+        assert_eq!(info.impls[0].name, "impl<'a> Point");
+        assert_eq!(info.enums[0].name, "Shape");
+        assert_eq!(info.traits[0].name, "Area");
+        assert!(info.functions.iter().any(|f| f.name == "main" && f.parent.is_none()));
+    }
+
+    #[test]
+    fn names_go_methods_by_their_receiver() {
+        let source = "package main\n\ntype Server struct {\n\taddr string\n}\n\nfunc (s *Server) Start() {}\n";
+        let info = parse_file(&write_source("server.go", source), None).unwrap();
+
+        assert_eq!(info.structs[0].name, "Server");
+        assert_eq!(info.structs[0].fields, ["addr"]);
+        assert_eq!(info.structs[0].methods[0].name, "Start");
+        assert_eq!(info.structs[0].methods[0].parent.as_deref(), Some("Server"));
+    }
+
+    #[test]
+    fn finds_c_function_names_inside_declarators() {
+        let source = "static char *copy(const char *s) {\n    return 0;\n}\n";
+        let info = parse_file(&write_source("util.c", source), None).unwrap();
+
+        assert_eq!(info.functions[0].name, "copy");
+    }
+}
@@ -0,0 +1,143 @@
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use globset::{GlobBuilder, GlobSet, GlobSetBuilder};
+use ignore::Match;
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use walkdir::{DirEntry, WalkDir};
+
+/// Directories that are never listed or searched: build output and version control metadata
+pub const SKIPPED_DIRS: &[&str] = &["target", ".git", ".hg", ".svn", ".jj"];
+
+/// Paths returned when `find_files` isn't given a limit
+pub const DEFAULT_MAX_FOUND_FILES: usize = 200;
+
+/// Ignore files read in every directory, like ripgrep does
+const IGNORE_FILES: &[&str] = &[".gitignore", ".ignore"];
+
+/// Walks a directory tree in file name order, leaving out `SKIPPED_DIRS` and everything the
+/// `.gitignore` and `.ignore` files exclude
+///
+/// Ignore files in the directories above `root`, up to the enclosing repository, apply too, so
+/// walking a subdirectory gives the same result as walking the whole repository.
+pub struct FilteredWalk {
+    inner: walkdir::IntoIter,
+    /// Ignore rules with the depth of the directory that defined them, innermost last
+    matchers: Vec<(usize, Gitignore)>,
+}
+
+impl FilteredWalk {
+    pub fn new(root: &Path) -> Self {
+        let walker = WalkDir::new(root).sort_by_file_name();
+
+        // Ancestors up to the repository root, outermost first; their rules never go out of scope
+        let mut ancestors = Vec::new();
+        for dir in root.ancestors().skip(1) {
+            ancestors.push(dir);
+            if dir.join(".git").exists() {
+                break;
+            }
+        }
+        if !ancestors.last().is_some_and(|dir| dir.join(".git").exists()) {
+            ancestors.clear();
+        }
+        let matchers = ancestors.into_iter().rev().filter_map(load_ignore_files).map(|m| (0, m)).collect();
+
+        Self { inner: walker.into_iter(), matchers }
+    }
+
+    fn is_ignored(&self, entry: &DirEntry) -> bool {
+        let is_dir = entry.file_type().is_dir();
+        if entry.depth() == 0 {
+            return false;
+        }
+        if is_dir && SKIPPED_DIRS.contains(&entry.file_name().to_string_lossy().as_ref()) {
+            return true;
+        }
+        // The innermost rule that matches decides, so a nested `!pattern` can re-include a path
+        for (_, matcher) in self.matchers.iter().rev() {
+            match matcher.matched(entry.path(), is_dir) {
+                Match::Ignore(_) => return true,
+                Match::Whitelist(_) => return false,
+                Match::None => {}
+            }
+        }
+        false
+    }
+}
+
+impl Iterator for FilteredWalk {
+    type Item = DirEntry;
+
+    fn next(&mut self) -> Option<DirEntry> {
+        loop {
+            let entry = match self.inner.next()? {
+                Ok(entry) => entry,
+                Err(_) => continue, // Unreadable entries are left out
+            };
+
+            // Rules of directories the walk has left no longer apply
+            let depth = entry.depth();
+            while self.matchers.last().is_some_and(|(d, _)| *d >= depth && *d > 0) {
+                self.matchers.pop();
+            }
+
+            if self.is_ignored(&entry) {
+                if entry.file_type().is_dir() {
+                    self.inner.skip_current_dir();
+                }
+                continue;
+            }
+            if entry.file_type().is_dir()
+                && let Some(matcher) = load_ignore_files(entry.path())
+            {
+                self.matchers.push((depth, matcher));
+            }
+            return Some(entry);
+        }
+    }
+}
+
+/// Reads the ignore files of a directory, `None` if it has none
+fn load_ignore_files(dir: &Path) -> Option<Gitignore> {
+    let mut builder = GitignoreBuilder::new(dir);
+    let mut found = false;
+    for name in IGNORE_FILES {
+        let path = dir.join(name);
+        if path.is_file() {
+            found = true;
+            // Malformed lines are skipped; the valid ones still apply
+            let _ = builder.add(path);
+        }
+    }
+    if found { builder.build().ok() } else { None }
+}
+
+/// Compiles globs matched against relative paths
+///
+/// As in a shell, `*` and `?` don't match `/`, so `src/*.rs` only matches the files directly in
+/// `src`, while `src/**/*.rs` matches them at any depth.
+pub fn glob_set<S: AsRef<str>>(patterns: impl IntoIterator<Item = S>) -> Result<GlobSet, globset::Error> {
+    let mut builder = GlobSetBuilder::new();
+    for pattern in patterns {
+        builder.add(GlobBuilder::new(pattern.as_ref()).literal_separator(true).build()?);
+    }
+    builder.build()
+}
+
+/// Files below `root` whose path relative to `root` matches one of the globs (see `glob_set`),
+/// most recently modified first
+pub fn find_files(root: &Path, patterns: &[String]) -> Result<Vec<PathBuf>, globset::Error> {
+    let globs = glob_set(patterns)?;
+
+    let mut found: Vec<(SystemTime, PathBuf)> = FilteredWalk::new(root)
+        .filter(|entry| entry.file_type().is_file())
+        .filter(|entry| globs.is_match(entry.path().strip_prefix(root).unwrap_or(entry.path())))
+        .map(|entry| {
+            let modified = entry.metadata().ok().and_then(|m| m.modified().ok()).unwrap_or(SystemTime::UNIX_EPOCH);
+            (modified, entry.into_path())
+        })
+        .collect();
+    found.sort_by(|a, b| b.0.cmp(&a.0).then_with(|| a.1.cmp(&b.1)));
+    Ok(found.into_iter().map(|(_, path)| path).collect())
+}